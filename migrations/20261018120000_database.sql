@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE user_config ADD COLUMN speed REAL NOT NULL DEFAULT 1.0;
+ALTER TABLE user_config ADD COLUMN pitch REAL NOT NULL DEFAULT 0.0;
+ALTER TABLE user_config ADD COLUMN intonation REAL NOT NULL DEFAULT 1.0;
+ALTER TABLE user_config ADD COLUMN volume REAL NOT NULL DEFAULT 1.0;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE dict ADD COLUMN match_mode INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE dict_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    word TEXT NOT NULL,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    author_id INTEGER NOT NULL,
+    origin_guild_id INTEGER,
+    created_at INTEGER NOT NULL,
+    old_read_word TEXT,
+    old_match_mode INTEGER,
+    new_read_word TEXT,
+    new_match_mode INTEGER,
+    undo_of INTEGER,
+    undone INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX dict_history_word ON dict_history (word, guild_id, user_id);
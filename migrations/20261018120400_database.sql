@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE permission_rule (
+    guild_id INTEGER NOT NULL,
+    command INTEGER NOT NULL,
+    permissions INTEGER,
+    role_id INTEGER,
+    PRIMARY KEY (guild_id, command)
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE read_ignore (
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, user_id)
+);
+CREATE TABLE guild_config (
+    guild_id INTEGER PRIMARY KEY NOT NULL,
+    skip_bots INTEGER NOT NULL DEFAULT 0
+);
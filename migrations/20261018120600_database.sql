@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE guild_config ADD COLUMN max_chars INTEGER;
+ALTER TABLE guild_config ADD COLUMN max_lines INTEGER;
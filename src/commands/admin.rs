@@ -0,0 +1,60 @@
+use crate::{lib::generator::reload_voice_types, Context};
+use anyhow::Result;
+use tracing::info;
+
+#[poise::command(
+    slash_command,
+    owners_only,
+    description_localized("ja", "音声合成エンジンからボイスタイプの一覧を読み込み直します")
+)]
+pub async fn reload_voices(ctx: Context<'_>) -> Result<()> {
+    ctx.defer().await?;
+    let data = ctx.data();
+    let stale = reload_voice_types(&data.generators, &data.voice_types, &data.database).await?;
+    let count = data.voice_types.lock().await.len();
+    let mut text = format!("{} 個のボイスタイプを読み込んだよ", count);
+    // 他のサーバーの人も含まれるので、誰かはログにだけ出す
+    if !stale.is_empty() {
+        info!("users with unavailable voice type: {:?}", stale);
+        text.push_str(&format!(
+            "\n{} 人のボイスタイプが見つからなくなったよ",
+            stale.len()
+        ));
+    }
+    ctx.say(text).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    owners_only,
+    description_localized("ja", "合成した音声のキャッシュの使われ方を表示します")
+)]
+pub async fn cache_stats(ctx: Context<'_>) -> Result<()> {
+    let cache = match ctx.data().generators.cache() {
+        Some(cache) => cache,
+        None => {
+            ctx.say("キャッシュは使ってないよ").await?;
+            return Ok(());
+        }
+    };
+    let stats = cache.stats();
+    let lookups = stats.hits + stats.misses;
+    let hit_rate = if lookups == 0 {
+        0.
+    } else {
+        stats.hits as f64 * 100. / lookups as f64
+    };
+    ctx.say(format!(
+        "{} 件 ({:.1} / {:.1} MiB)\nヒット {} 回、ミス {} 回 (ヒット率 {:.1}%)、追い出し {} 回",
+        stats.entries,
+        stats.bytes as f64 / (1024. * 1024.),
+        cache.max_bytes() as f64 / (1024. * 1024.),
+        stats.hits,
+        stats.misses,
+        hit_rate,
+        stats.evictions
+    ))
+    .await?;
+    Ok(())
+}
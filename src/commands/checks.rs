@@ -0,0 +1,78 @@
+use crate::{
+    lib::{
+        db::{CommandGroup, PermissionDB},
+        permission,
+    },
+    Context,
+};
+use anyhow::{anyhow, Result};
+
+/// サーバーの設定に従って commands をすべて使えるか確かめる
+///
+/// 使えないときは on_error で返事をするために、満たしていない設定をすべて invocation_data に入れておく。
+/// bot のオーナーはいつでも使える
+async fn check(ctx: Context<'_>, commands: &[CommandGroup]) -> Result<bool> {
+    if ctx.framework().options().owners.contains(&ctx.author().id) {
+        return Ok(true);
+    }
+    let guild_id = match ctx.guild_id() {
+        Some(guild_id) => guild_id.0 as i64,
+        // DM では管理者用コマンド以外は誰でも使える
+        None => return Ok(!commands.contains(&CommandGroup::Admin)),
+    };
+    let member = ctx
+        .author_member()
+        .await
+        .ok_or_else(|| anyhow!("member not found"))?;
+    let permissions = match member.permissions {
+        Some(permissions) => permissions,
+        None => member.permissions(ctx.discord())?,
+    };
+    let mut unmet = Vec::new();
+    for &command in commands {
+        let rule = ctx
+            .data()
+            .database
+            .get_permission_rule(guild_id, command)
+            .await?
+            .unwrap_or_else(|| permission::default_rule(guild_id, command));
+        if !permission::allows(&rule, permissions, &member.roles) {
+            unmet.push(rule);
+        }
+    }
+    if unmet.is_empty() {
+        Ok(true)
+    } else {
+        ctx.set_invocation_data(unmet).await;
+        Ok(false)
+    }
+}
+
+pub async fn can_add(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Add]).await
+}
+
+pub async fn can_rem(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Rem]).await
+}
+
+/// 単語の登録と削除の両方をするコマンド用
+pub async fn can_add_and_rem(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Add, CommandGroup::Rem]).await
+}
+
+pub async fn can_mute(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Mute]).await
+}
+
+pub async fn can_leave(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Leave]).await
+}
+
+pub async fn is_admin(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Admin]).await
+}
+
+pub async fn can_ignore(ctx: Context<'_>) -> Result<bool> {
+    check(ctx, &[CommandGroup::Ignore]).await
+}
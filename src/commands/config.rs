@@ -0,0 +1,206 @@
+use crate::{
+    commands::checks::is_admin,
+    lib::{
+        db::{CommandGroup, PermissionDB, PermissionRule, ReadFilterDB, ReadLimit, ReadLimitDB},
+        permission::{self, RequiredPermission},
+    },
+    Context,
+};
+use anyhow::{anyhow, Result};
+use poise::serenity_prelude as serenity;
+
+const COMMAND_GROUPS: [CommandGroup; 6] = [
+    CommandGroup::Add,
+    CommandGroup::Rem,
+    CommandGroup::Mute,
+    CommandGroup::Leave,
+    CommandGroup::Admin,
+    CommandGroup::Ignore,
+];
+
+#[poise::command(
+    slash_command,
+    subcommands("config_permissions", "config_skip_bots", "config_max_length"),
+    description_localized("ja", "サーバーの設定を変更します")
+)]
+pub async fn config(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "permissions",
+    check = "is_admin",
+    description_localized("ja", "コマンドを使うのに必要な権限かロールを設定します")
+)]
+pub async fn config_permissions(
+    ctx: Context<'_>,
+    #[description = "command"] command: Option<CommandGroup>,
+    #[description = "permission"] permission: Option<RequiredPermission>,
+    #[description = "role"] role: Option<serenity::Role>,
+    #[description = "reset"] reset: Option<bool>,
+) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("サーバーの中で使ってね"))?
+        .0 as i64;
+    let database = &ctx.data().database;
+    let locale = ctx.locale();
+    let japanese = permission::is_japanese(locale);
+    // command を指定しなければ今の設定を表示する。reset を True にすると既定値に戻す
+    let text = match command {
+        None => {
+            let rules = database.get_permission_rules(guild_id).await?;
+            COMMAND_GROUPS
+                .iter()
+                .map(|command| {
+                    let rule = rules
+                        .iter()
+                        .find(|rule| rule.command == *command)
+                        .cloned()
+                        .unwrap_or_else(|| permission::default_rule(guild_id, *command));
+                    format!(
+                        "{}: {}",
+                        command_name(*command, locale),
+                        permission::describe(&rule, locale)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Some(command) if reset.unwrap_or(false) => {
+            database.remove_permission_rule(guild_id, command).await?;
+            let rule = permission::default_rule(guild_id, command);
+            if japanese {
+                format!(
+                    "{} を使える人を最初の設定 ({}) に戻したよ",
+                    command_name(command, locale),
+                    permission::describe(&rule, locale)
+                )
+            } else {
+                format!(
+                    "Reset who can use {} to the default ({}).",
+                    command_name(command, locale),
+                    permission::describe(&rule, locale)
+                )
+            }
+        }
+        Some(command) => {
+            if permission.is_none() && role.is_none() {
+                return Err(anyhow!("permission か role を指定してね"));
+            }
+            let rule = PermissionRule {
+                guild_id,
+                command,
+                permissions: permission.map(|permission| permission.permissions().bits() as i64),
+                role_id: role.map(|role| role.id.0 as i64),
+            };
+            database.update_permission_rule(&rule).await?;
+            if japanese {
+                format!(
+                    "これからは {} を使うには {} が必要だよ",
+                    command_name(command, locale),
+                    permission::describe(&rule, locale)
+                )
+            } else {
+                format!(
+                    "From now on, {} requires {}.",
+                    command_name(command, locale),
+                    permission::describe(&rule, locale)
+                )
+            }
+        }
+    };
+    ctx.send(|m| {
+        m.content(text)
+            .allowed_mentions(|mentions| mentions.empty_roles())
+    })
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "skip_bots",
+    check = "is_admin",
+    description_localized("ja", "bot や webhook の発言を読み上げるかを設定します")
+)]
+pub async fn config_skip_bots(
+    ctx: Context<'_>,
+    #[description = "enabled"] enabled: bool,
+) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("サーバーの中で使ってね"))?
+        .0 as i64;
+    ctx.data().database.set_skip_bots(guild_id, enabled).await?;
+    if enabled {
+        ctx.say("これからは bot と webhook の発言は読まないよ")
+            .await?;
+    } else {
+        ctx.say("これからは bot と webhook の発言も読むね").await?;
+    }
+    Ok(())
+}
+
+/// これより短くすると名前だけで終わってしまう
+const MIN_MAX_CHARS: u32 = 10;
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename = "max_length",
+    check = "is_admin",
+    description_localized("ja", "1 回の発言で読み上げる文字数と行数の上限を設定します")
+)]
+pub async fn config_max_length(
+    ctx: Context<'_>,
+    #[description = "characters"] chars: Option<u32>,
+    #[description = "lines"] lines: Option<u32>,
+) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("サーバーの中で使ってね"))?
+        .0 as i64;
+    let database = &ctx.data().database;
+    let mut limit = database.get_read_limit(guild_id).await?;
+    // どちらも指定しなければ今の設定を表示する。0 にすると上限なし
+    if chars.is_some() || lines.is_some() {
+        if let Some(chars) = chars {
+            if chars != 0 && chars < MIN_MAX_CHARS {
+                return Err(anyhow!("文字数は {} 文字以上にしてね", MIN_MAX_CHARS));
+            }
+            limit.max_chars = Some(chars as usize).filter(|&chars| chars > 0);
+        }
+        if let Some(lines) = lines {
+            limit.max_lines = Some(lines as usize).filter(|&lines| lines > 0);
+        }
+        database.set_read_limit(guild_id, limit).await?;
+    }
+    ctx.say(describe_limit(limit)).await?;
+    Ok(())
+}
+
+fn describe_limit(limit: ReadLimit) -> String {
+    let describe = |max: Option<usize>, unit: &str| match max {
+        Some(max) => format!("{}{}", max, unit),
+        None => "上限なし".to_string(),
+    };
+    format!(
+        "文字数: {}\n行数: {}\nこれより長い発言は途中まで読んで「以下省略」って言うよ",
+        describe(limit.max_chars, " 文字"),
+        describe(limit.max_lines, " 行")
+    )
+}
+
+fn command_name(command: CommandGroup, locale: Option<&str>) -> &'static str {
+    if permission::is_japanese(locale) {
+        command
+            .localized_name("ja")
+            .unwrap_or_else(|| command.name())
+    } else {
+        command.name()
+    }
+}
@@ -1,29 +1,372 @@
 type Context<'a> = poise::Context<'a, Data, anyhow::Error>;
 use anyhow::{anyhow, Result};
+use poise::serenity_prelude as serenity;
 
-use crate::{lib::db::DictDB, Data, Dict};
+use crate::{
+    commands::checks::{can_add, can_add_and_rem, can_rem},
+    lib::{
+        db::{DictDB, DictEditor, DictScope, MatchMode},
+        dict_file::{self, DictFormat, ImportPlan},
+        dict_matcher, dict_view,
+    },
+    Data, Dict,
+};
 
-#[poise::command(slash_command, description_localized("ja", "辞書に単語を登録します"))]
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum DictScopeChoice {
+    #[name = "server"]
+    Server,
+    #[name = "personal"]
+    Personal,
+    #[name = "global"]
+    Global,
+}
+
+/// 登録先の指定を DictScope にする。指定がなければこのサーバーの辞書になる
+///
+/// 全体の辞書は bot のオーナーしか変更できない
+fn resolve_scope(ctx: Context<'_>, choice: Option<DictScopeChoice>) -> Result<DictScope> {
+    match choice.unwrap_or(DictScopeChoice::Server) {
+        DictScopeChoice::Server => {
+            let guild_id = ctx
+                .guild_id()
+                .ok_or_else(|| anyhow!("サーバーの中で使ってね"))?;
+            Ok(DictScope::Guild(guild_id.0 as i64))
+        }
+        DictScopeChoice::Personal => Ok(DictScope::User(ctx.author().id.0 as i64)),
+        DictScopeChoice::Global => {
+            if ctx.framework().options().owners.contains(&ctx.author().id) {
+                Ok(DictScope::Global)
+            } else {
+                Err(anyhow!("全体の辞書は bot の管理者しか変えられないよ"))
+            }
+        }
+    }
+}
+
+/// 辞書の変更履歴に残す、コマンドを使った人と場所
+fn editor(ctx: Context<'_>) -> DictEditor {
+    DictEditor {
+        user_id: ctx.author().id.0 as i64,
+        guild_id: ctx.guild_id().map(|guild_id| guild_id.0 as i64),
+    }
+}
+
+#[poise::command(
+    slash_command,
+    check = "can_add",
+    description_localized("ja", "辞書に単語を登録します")
+)]
 pub async fn add(
     ctx: Context<'_>,
     #[description = "before"] before: String,
     #[description = "after"] after: String,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+    #[description = "match_mode"] match_mode: Option<MatchMode>,
 ) -> Result<()> {
+    let scope = resolve_scope(ctx, scope)?;
     let dict = Dict {
         word: before.to_string(),
         read_word: after.to_string(),
+        match_mode: match_mode.unwrap_or_default(),
     };
-    ctx.data().database.update_dict(&dict).await?;
+    ctx.data()
+        .dictionary
+        .update_dict(scope, &dict, editor(ctx))
+        .await?;
     ctx.say(format!("これからは {} を {} って読むね", before, after))
         .await?;
     Ok(())
 }
-#[poise::command(slash_command, description_localized("ja", "辞書から単語を削除します"))]
-pub async fn rem(ctx: Context<'_>, #[description = "word"] word: String) -> Result<()> {
-    if (ctx.data().database.remove(&word).await).is_ok() {
+#[poise::command(
+    slash_command,
+    check = "can_rem",
+    description_localized("ja", "辞書から単語を削除します")
+)]
+pub async fn rem(
+    ctx: Context<'_>,
+    #[description = "word"] word: String,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let scope = resolve_scope(ctx, scope)?;
+    if (ctx
+        .data()
+        .dictionary
+        .remove(scope, &word, editor(ctx))
+        .await)
+        .is_ok()
+    {
         ctx.say(format!("これからは {} って読むね", word)).await?;
         Ok(())
     } else {
         Err(anyhow!("その単語は登録されてないよ！"))
     }
 }
+
+#[poise::command(
+    slash_command,
+    subcommands(
+        "dict_list",
+        "dict_search",
+        "dict_show",
+        "dict_export",
+        "dict_import",
+        "dict_history",
+        "dict_undo"
+    ),
+    description_localized("ja", "辞書に登録されている単語を確認、読み書きします")
+)]
+pub async fn dict(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "list",
+    description_localized("ja", "辞書に登録されている単語の一覧を表示します")
+)]
+pub async fn dict_list(
+    ctx: Context<'_>,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let ephemeral = matches!(scope, Some(DictScopeChoice::Personal));
+    let scope = list_scope(ctx, scope)?;
+    let dicts = ctx.data().dictionary.get_dict_all(scope).await?;
+    let view = dict_view::list_page(scope, dicts, 0);
+    ctx.send(|m| {
+        m.embeds.push(view.embed);
+        m.components = Some(view.components);
+        m.ephemeral(ephemeral)
+    })
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "search",
+    description_localized("ja", "単語か読みに指定した文字列を含むものを検索します")
+)]
+pub async fn dict_search(
+    ctx: Context<'_>,
+    #[description = "query"] query: String,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let ephemeral = matches!(scope, Some(DictScopeChoice::Personal));
+    let scope = list_scope(ctx, scope)?;
+    let found = dict_view::search(ctx.data().dictionary.get_dict_all(scope).await?, &query);
+    let embed = dict_view::search_result(scope, &query, &found);
+    ctx.send(|m| {
+        m.embeds.push(embed);
+        m.ephemeral(ephemeral)
+    })
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "show",
+    description_localized("ja", "単語がどの辞書でどう読まれるかを表示します")
+)]
+pub async fn dict_show(ctx: Context<'_>, #[description = "word"] word: String) -> Result<()> {
+    let mut scopes = vec![DictScope::User(ctx.author().id.0 as i64)];
+    if let Some(guild_id) = ctx.guild_id() {
+        scopes.push(DictScope::Guild(guild_id.0 as i64));
+    }
+    scopes.push(DictScope::Global);
+    let mut lines = Vec::new();
+    for scope in scopes {
+        if let Ok(dict) = ctx.data().dictionary.get_dict(scope, &word).await {
+            lines.push(format!(
+                "{}: {}",
+                dict_view::scope_name(scope),
+                dict_view::describe(&dict)
+            ));
+        }
+    }
+    if lines.is_empty() {
+        return Err(anyhow!("その単語は登録されてないよ！"));
+    }
+    // 個人 > サーバー > 全体 の順に優先されるので、先頭のものが実際の読みになる
+    lines[0].push_str(" (この読みを使うよ)");
+    ctx.send(|m| {
+        m.content(format!("{}\n{}", word, lines.join("\n")))
+            .ephemeral(true)
+    })
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "export",
+    description_localized("ja", "辞書をファイルに書き出します")
+)]
+pub async fn dict_export(
+    ctx: Context<'_>,
+    #[description = "format"] format: Option<DictFormat>,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let ephemeral = matches!(scope, Some(DictScopeChoice::Personal));
+    let scope = list_scope(ctx, scope)?;
+    let format = format.unwrap_or(DictFormat::Json);
+    let mut dicts = ctx.data().dictionary.get_dict_all(scope).await?;
+    dicts.sort_by(|a, b| a.word.cmp(&b.word));
+    let (content, skipped) = dict_file::export(format, &dicts)?;
+    let mut text = format!(
+        "{} の {} 件を書き出したよ",
+        dict_view::scope_name(scope),
+        dicts.len() - skipped
+    );
+    if skipped > 0 {
+        text.push_str(&format!(
+            "\n読みがかなだけじゃない {} 件は VOICEVOX の辞書に入れられないから書き出してないよ",
+            skipped
+        ));
+    }
+    ctx.send(|m| {
+        m.content(text)
+            .attachment(serenity::AttachmentType::Bytes {
+                data: content.into_bytes().into(),
+                filename: format.filename().to_string(),
+            })
+            .ephemeral(ephemeral)
+    })
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum ImportMode {
+    /// 登録済みの単語を残したまま取り込む
+    #[name = "merge"]
+    Merge,
+    /// ファイルにない単語を削除して、ファイルの中身と同じにする
+    #[name = "replace"]
+    Replace,
+}
+
+/// 読み込むファイルの大きさの上限
+const MAX_IMPORT_SIZE: u64 = 1024 * 1024;
+
+/// 取り込み結果に表示する、読みが変わる単語の数の上限
+const MAX_REPORTED_CHANGES: usize = 20;
+
+#[poise::command(
+    slash_command,
+    rename = "import",
+    check = "can_add_and_rem",
+    description_localized(
+        "ja",
+        "JSON, CSV, VOICEVOX のユーザー辞書のファイルから辞書に単語を取り込みます"
+    )
+)]
+pub async fn dict_import(
+    ctx: Context<'_>,
+    #[description = "file"] file: serenity::Attachment,
+    #[description = "mode"] mode: Option<ImportMode>,
+    #[description = "dry_run"] dry_run: Option<bool>,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let scope = resolve_scope(ctx, scope)?;
+    let replace = matches!(mode, Some(ImportMode::Replace));
+    let dry_run = dry_run.unwrap_or(false);
+    if file.size > MAX_IMPORT_SIZE {
+        return Err(anyhow!("ファイルが大きすぎるよ"));
+    }
+    ctx.defer().await?;
+    let content = String::from_utf8(file.download().await?)
+        .map_err(|_| anyhow!("UTF-8 のファイルを選んでね"))?;
+    let incoming = dict_file::parse(&file.filename, &content)?;
+    for dict in incoming.iter() {
+        dict_matcher::validate(dict)?;
+    }
+    let dictionary = &ctx.data().dictionary;
+    let existing = dictionary.get_dict_all(scope).await?;
+    let plan = ImportPlan::new(&existing, &incoming, replace);
+    if !dry_run {
+        dictionary
+            .import_dict(scope, &plan.writes(), &plan.removed, editor(ctx))
+            .await?;
+    }
+    let text = format!(
+        "{} に {} 件を{}{}\n追加 {} 件、変更 {} 件、変化なし {} 件、削除 {} 件",
+        dict_view::scope_name(scope),
+        incoming.len(),
+        if replace { "置き換え" } else { "追加し" },
+        if dry_run {
+            "た場合の結果だよ。辞書はまだ変えてないよ"
+        } else {
+            "たよ"
+        },
+        plan.added.len(),
+        plan.changed.len(),
+        plan.unchanged,
+        plan.removed.len()
+    );
+    let text = dict_view::import_changes(text, &plan.changed, MAX_REPORTED_CHANGES);
+    ctx.say(text).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "history",
+    description_localized("ja", "単語の変更履歴を表示します")
+)]
+pub async fn dict_history(
+    ctx: Context<'_>,
+    #[description = "word"] word: String,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let ephemeral = matches!(scope, Some(DictScopeChoice::Personal));
+    let scope = list_scope(ctx, scope)?;
+    let history = ctx
+        .data()
+        .dictionary
+        .get_dict_history(scope, &word, dict_view::HISTORY_SIZE as i64)
+        .await?;
+    let embed = dict_view::history(scope, &word, &history);
+    ctx.send(|m| {
+        m.embeds.push(embed);
+        m.allowed_mentions(|mentions| mentions.empty_users())
+            .ephemeral(ephemeral)
+    })
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "undo",
+    check = "can_add_and_rem",
+    description_localized("ja", "単語の最後の変更を取り消して、前の読みに戻します")
+)]
+pub async fn dict_undo(
+    ctx: Context<'_>,
+    #[description = "word"] word: String,
+    #[description = "scope"] scope: Option<DictScopeChoice>,
+) -> Result<()> {
+    let scope = resolve_scope(ctx, scope)?;
+    let undone = ctx
+        .data()
+        .dictionary
+        .undo_dict(scope, &word, editor(ctx))
+        .await
+        .map_err(|_| anyhow!("取り消せる変更がないよ"))?;
+    let text = match undone.before() {
+        Some(dict) => format!("{} の読みを元に戻したよ", dict_view::describe(&dict)),
+        None => format!("{} の登録を取り消したよ", dict_view::ellipsize(&word)),
+    };
+    ctx.say(text).await?;
+    Ok(())
+}
+
+/// 一覧表示する辞書を決める。全体の辞書は誰でも見られる
+fn list_scope(ctx: Context<'_>, choice: Option<DictScopeChoice>) -> Result<DictScope> {
+    match choice {
+        Some(DictScopeChoice::Global) => Ok(DictScope::Global),
+        choice => resolve_scope(ctx, choice),
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{commands::checks::can_ignore, lib::db::ReadFilterDB, Context};
+use anyhow::{anyhow, Result};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    check = "can_ignore",
+    description_localized(
+        "ja",
+        "指定した人の発言を読み上げないようにします。もう一度使うと元に戻します。引数を与えなかった場合、一覧を表示します"
+    )
+)]
+pub async fn ignore(
+    ctx: Context<'_>,
+    #[description = "user"] user: Option<serenity::User>,
+) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("サーバーの中で使ってね"))?
+        .0 as i64;
+    let database = &ctx.data().database;
+    let text = match user {
+        None => {
+            let users = database.get_ignored_users(guild_id).await?;
+            if users.is_empty() {
+                "読み上げない人はいないよ".to_string()
+            } else {
+                let mentions = users
+                    .iter()
+                    .map(|user_id| format!("<@{}>", user_id))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{} の発言は読んでないよ", mentions)
+            }
+        }
+        Some(user) => {
+            let user_id = user.id.0 as i64;
+            if database.set_ignored(guild_id, user_id, true).await? {
+                format!("これからは {} の発言は読まないよ", user.mention())
+            } else {
+                database.set_ignored(guild_id, user_id, false).await?;
+                format!("これからは {} の発言も読むね", user.mention())
+            }
+        }
+    };
+    ctx.send(|m| {
+        m.content(text)
+            .allowed_mentions(|mentions| mentions.empty_users())
+    })
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    description_localized(
+        "ja",
+        "自分の発言をすべてのサーバーで読み上げないようにします。もう一度使うと元に戻します"
+    )
+)]
+pub async fn optout(ctx: Context<'_>) -> Result<()> {
+    let user_id = ctx.author().id.0 as i64;
+    let database = &ctx.data().database;
+    let text = if database.set_ignored(0, user_id, true).await? {
+        "これからはあなたの発言は読まないよ"
+    } else {
+        database.set_ignored(0, user_id, false).await?;
+        "これからはあなたの発言も読むね"
+    };
+    ctx.send(|m| m.content(text).ephemeral(true)).await?;
+    Ok(())
+}
@@ -1,7 +1,9 @@
-use crate::TrackEndNotifier;
+use crate::{
+    commands::checks::{can_leave, can_mute},
+    lib::{queue, session::Session},
+};
 type Context<'a> = poise::Context<'a, crate::Data, anyhow::Error>;
 use anyhow::{anyhow, Result};
-use songbird::{Event, TrackEvent};
 #[poise::command(slash_command, description_localized("ja", "VCに参加します"))]
 pub async fn join(ctx: Context<'_>) -> Result<()> {
     let guild_id = ctx
@@ -25,13 +27,20 @@ pub async fn join(ctx: Context<'_>) -> Result<()> {
     err?;
     let mut handle = handle_lock.lock().await;
     handle.deafen(true).await?;
-    handle.add_global_event(Event::Track(TrackEvent::End), TrackEndNotifier);
-    *ctx.data().read_channel_id.lock().await = Some(text_channel_id);
+    ctx.data()
+        .sessions
+        .lock()
+        .await
+        .insert(guild_id, Session::new(voice_channel_id, text_channel_id));
     ctx.say("こんにちは").await?;
     Ok(())
 }
 
-#[poise::command(slash_command, description_localized("ja", "VCから抜けます"))]
+#[poise::command(
+    slash_command,
+    check = "can_leave",
+    description_localized("ja", "VCから抜けます")
+)]
 pub async fn leave(ctx: Context<'_>) -> Result<()> {
     let guild_id = ctx
         .guild_id()
@@ -42,6 +51,7 @@ pub async fn leave(ctx: Context<'_>) -> Result<()> {
     let has_handler = manager.get(guild_id).is_some();
     if has_handler {
         manager.remove(guild_id).await?;
+        ctx.data().sessions.lock().await.remove(&guild_id);
         ctx.say("ばいばい").await?;
         Ok(())
     } else {
@@ -49,7 +59,11 @@ pub async fn leave(ctx: Context<'_>) -> Result<()> {
     }
 }
 
-#[poise::command(slash_command, description_localized("ja", "botをミュートします"))]
+#[poise::command(
+    slash_command,
+    check = "can_mute",
+    description_localized("ja", "botをミュートします")
+)]
 pub async fn mute(ctx: Context<'_>) -> Result<()> {
     let guild_id = ctx
         .guild_id()
@@ -74,6 +88,7 @@ pub async fn mute(ctx: Context<'_>) -> Result<()> {
 
 #[poise::command(
     slash_command,
+    check = "can_mute",
     description_localized("ja", "botのミュートを解除します")
 )]
 pub async fn unmute(ctx: Context<'_>) -> Result<()> {
@@ -94,3 +109,76 @@ pub async fn unmute(ctx: Context<'_>) -> Result<()> {
         Ok(())
     }
 }
+
+#[poise::command(
+    slash_command,
+    check = "can_mute",
+    description_localized("ja", "読み上げ中の発言を飛ばします")
+)]
+pub async fn skip(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let manager = songbird::get(ctx.discord())
+        .await
+        .ok_or_else(|| anyhow!("Songbird Voice client placed in at initialisation."))?;
+    let handler_lock = manager
+        .get(guild_id)
+        .ok_or_else(|| anyhow!("ボイスチャンネルに入ってないよ"))?;
+    let handler = handler_lock.lock().await;
+    if handler.queue().is_empty() {
+        return Err(anyhow!("読み上げてないよ"));
+    }
+    handler.queue().skip()?;
+    ctx.say("飛ばしたよ").await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    check = "can_mute",
+    description_localized("ja", "読み上げ中の発言と読み上げ待ちをすべて消します")
+)]
+pub async fn stop(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let manager = songbird::get(ctx.discord())
+        .await
+        .ok_or_else(|| anyhow!("Songbird Voice client placed in at initialisation."))?;
+    let handler_lock = manager
+        .get(guild_id)
+        .ok_or_else(|| anyhow!("ボイスチャンネルに入ってないよ"))?;
+    let handler = handler_lock.lock().await;
+    let len = handler.queue().len();
+    if len == 0 {
+        return Err(anyhow!("読み上げてないよ"));
+    }
+    handler.queue().stop();
+    ctx.say(format!("{} 件の読み上げを止めたよ", len)).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    description_localized("ja", "読み上げ待ちの発言を表示します")
+)]
+pub async fn queue(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let manager = songbird::get(ctx.discord())
+        .await
+        .ok_or_else(|| anyhow!("Songbird Voice client placed in at initialisation."))?;
+    let handler_lock = manager
+        .get(guild_id)
+        .ok_or_else(|| anyhow!("ボイスチャンネルに入ってないよ"))?;
+    let handles = handler_lock.lock().await.queue().current_queue();
+    let text = queue::queue_text(&queue::utterances(&handles).await);
+    ctx.send(|m| {
+        m.content(text)
+            .allowed_mentions(|mentions| mentions.empty_parse())
+    })
+    .await?;
+    Ok(())
+}
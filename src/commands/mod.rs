@@ -1,3 +1,8 @@
+pub mod admin;
+pub mod checks;
+pub mod config;
 pub mod dict;
+pub mod ignore;
 pub mod meta;
+pub mod read;
 pub mod user_config;
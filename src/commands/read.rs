@@ -0,0 +1,109 @@
+use crate::Context;
+use anyhow::{anyhow, Result};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+
+#[poise::command(
+    slash_command,
+    subcommands("read_add", "read_remove", "read_list"),
+    description_localized("ja", "読み上げるテキストチャンネルを設定します")
+)]
+pub async fn read(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "add",
+    description_localized(
+        "ja",
+        "読み上げるテキストチャンネルを追加します。引数を与えなかった場合、このチャンネルを追加します"
+    )
+)]
+pub async fn read_add(
+    ctx: Context<'_>,
+    #[description = "channel"] channel: Option<serenity::GuildChannel>,
+) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let channel_id = channel.map_or_else(|| ctx.channel_id(), |channel| channel.id);
+    let inserted = ctx
+        .data()
+        .sessions
+        .lock()
+        .await
+        .get_mut(&guild_id)
+        .ok_or_else(|| anyhow!("ボイスチャンネルに入ってないよ"))?
+        .read_channel_ids
+        .insert(channel_id);
+    if inserted {
+        ctx.say(format!("これからは {} も読むね", channel_id.mention()))
+            .await?;
+        Ok(())
+    } else {
+        Err(anyhow!("{} はもう読んでるよ", channel_id.mention()))
+    }
+}
+
+#[poise::command(
+    slash_command,
+    rename = "remove",
+    description_localized(
+        "ja",
+        "読み上げるテキストチャンネルを削除します。引数を与えなかった場合、このチャンネルを削除します"
+    )
+)]
+pub async fn read_remove(
+    ctx: Context<'_>,
+    #[description = "channel"] channel: Option<serenity::GuildChannel>,
+) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let channel_id = channel.map_or_else(|| ctx.channel_id(), |channel| channel.id);
+    let removed = ctx
+        .data()
+        .sessions
+        .lock()
+        .await
+        .get_mut(&guild_id)
+        .ok_or_else(|| anyhow!("ボイスチャンネルに入ってないよ"))?
+        .read_channel_ids
+        .remove(&channel_id);
+    if removed {
+        ctx.say(format!("これからは {} は読まないよ", channel_id.mention()))
+            .await?;
+        Ok(())
+    } else {
+        Err(anyhow!("{} は読んでないよ", channel_id.mention()))
+    }
+}
+
+#[poise::command(
+    slash_command,
+    rename = "list",
+    description_localized("ja", "読み上げているテキストチャンネルの一覧を表示します")
+)]
+pub async fn read_list(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let channels = ctx
+        .data()
+        .sessions
+        .lock()
+        .await
+        .get(&guild_id)
+        .ok_or_else(|| anyhow!("ボイスチャンネルに入ってないよ"))?
+        .read_channel_ids
+        .iter()
+        .map(|channel_id| channel_id.mention().to_string())
+        .collect::<Vec<_>>();
+    if channels.is_empty() {
+        ctx.say("読んでいるチャンネルはないよ").await?;
+    } else {
+        ctx.say(format!("{} を読んでるよ", channels.join(" ")))
+            .await?;
+    }
+    Ok(())
+}
@@ -1,6 +1,9 @@
-use crate::{lib::db::UserConfigDB, Context};
+use crate::{
+    lib::{db::UserConfigDB, voice_picker},
+    Context,
+};
 use anyhow::{anyhow, Result};
-use poise::serenity_prelude::{CreateSelectMenu, InteractionResponseType};
+use poise::serenity_prelude::InteractionResponseType;
 
 pub async fn get_display_name(ctx: Context<'_>) -> Result<String> {
     Ok(ctx
@@ -18,23 +21,13 @@ pub async fn get_display_name(ctx: Context<'_>) -> Result<String> {
     description_localized("ja", "ボイスタイプ設定のためのメニューを表示します")
 )]
 pub async fn set_voice_type(ctx: Context<'_>) -> Result<()> {
-    let mut menus = Vec::new();
-    let voice_types = ctx.data().voice_types.lock().await;
-    for (idx, vec) in voice_types.chunks(25).enumerate() {
-        let menu = CreateSelectMenu::default()
-            .options(|os| {
-                for (speaker_idx, speaker) in vec.iter().enumerate() {
-                    os.create_option(|op| {
-                        op.label(format!("{} {}", speaker.name, speaker.style_name))
-                            .value(speaker_idx + 25 * idx)
-                    });
-                }
-                os
-            })
-            .custom_id(idx)
-            .clone();
-        menus.push(menu);
-    }
+    let view = {
+        let voice_types = ctx.data().voice_types.lock().await;
+        if voice_types.is_empty() {
+            return Err(anyhow!("ボイスタイプが見つからないよ"));
+        }
+        voice_picker::speaker_page(&voice_types, 0)
+    };
     if let poise::Context::Application(ctx) = ctx {
         if let poise::ApplicationCommandOrAutocompleteInteraction::ApplicationCommand(cmd) =
             ctx.interaction
@@ -43,12 +36,7 @@ pub async fn set_voice_type(ctx: Context<'_>) -> Result<()> {
                 response
                     .kind(InteractionResponseType::ChannelMessageWithSource)
                     .interaction_response_data(|msg| {
-                        msg.components(|c| {
-                            for menu in menus {
-                                c.create_action_row(|row| row.add_select_menu(menu));
-                            }
-                            c
-                        })
+                        msg.content(view.content).set_components(view.components)
                     })
             })
             .await?;
@@ -133,3 +121,108 @@ pub async fn set_nickname(
     ctx.say(say_text).await?;
     Ok(())
 }
+
+#[poise::command(
+    slash_command,
+    subcommands("voice_speed", "voice_pitch", "voice_intonation", "voice_volume"),
+    description_localized("ja", "読み上げの話速・音高・抑揚・音量を設定します")
+)]
+pub async fn voice(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "speed",
+    description_localized("ja", "話速を設定します。既定値は 1.0 です")
+)]
+pub async fn voice_speed(
+    ctx: Context<'_>,
+    #[description = "speed"]
+    #[min = 0.5]
+    #[max = 2.0]
+    speed: f64,
+) -> Result<()> {
+    let user_id = ctx.author().id.0 as i64;
+    let mut user_config = ctx
+        .data()
+        .database
+        .get_user_config_or_default(user_id)
+        .await?;
+    user_config.speed = speed;
+    ctx.data().database.update_user_config(&user_config).await?;
+    ctx.say(format!("話速を {} にしたよ", speed)).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "pitch",
+    description_localized("ja", "音高を設定します。既定値は 0.0 です")
+)]
+pub async fn voice_pitch(
+    ctx: Context<'_>,
+    #[description = "pitch"]
+    #[min = -0.15]
+    #[max = 0.15]
+    pitch: f64,
+) -> Result<()> {
+    let user_id = ctx.author().id.0 as i64;
+    let mut user_config = ctx
+        .data()
+        .database
+        .get_user_config_or_default(user_id)
+        .await?;
+    user_config.pitch = pitch;
+    ctx.data().database.update_user_config(&user_config).await?;
+    ctx.say(format!("音高を {} にしたよ", pitch)).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "intonation",
+    description_localized("ja", "抑揚を設定します。既定値は 1.0 です")
+)]
+pub async fn voice_intonation(
+    ctx: Context<'_>,
+    #[description = "intonation"]
+    #[min = 0.0]
+    #[max = 2.0]
+    intonation: f64,
+) -> Result<()> {
+    let user_id = ctx.author().id.0 as i64;
+    let mut user_config = ctx
+        .data()
+        .database
+        .get_user_config_or_default(user_id)
+        .await?;
+    user_config.intonation = intonation;
+    ctx.data().database.update_user_config(&user_config).await?;
+    ctx.say(format!("抑揚を {} にしたよ", intonation)).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    rename = "volume",
+    description_localized("ja", "音量を設定します。既定値は 1.0 です")
+)]
+pub async fn voice_volume(
+    ctx: Context<'_>,
+    #[description = "volume"]
+    #[min = 0.0]
+    #[max = 2.0]
+    volume: f64,
+) -> Result<()> {
+    let user_id = ctx.author().id.0 as i64;
+    let mut user_config = ctx
+        .data()
+        .database
+        .get_user_config_or_default(user_id)
+        .await?;
+    user_config.volume = volume;
+    ctx.data().database.update_user_config(&user_config).await?;
+    ctx.say(format!("音量を {} にしたよ", volume)).await?;
+    Ok(())
+}
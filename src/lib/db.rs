@@ -1,15 +1,14 @@
-use serde::Deserialize;
 use serenity::async_trait;
-use sqlx::{query, query_as};
-use tracing::info;
+use sqlx::{query, query_as, Sqlite, Transaction};
 
+use super::voice::SpeechParams;
 use crate::Dict;
 use anyhow::{anyhow, Result};
-const VOICEVOX: i64 = 0;
 #[async_trait]
 pub trait UserConfigDB {
     async fn get_user_config_or_default(&self, user_id: i64) -> Result<UserConfig>;
     async fn get_user_config(&self, user_id: i64) -> Result<UserConfig>;
+    async fn get_user_config_all(&self) -> Result<Vec<UserConfig>>;
     async fn update_user_config(&self, user_config: &UserConfig) -> Result<u64>;
 }
 
@@ -21,6 +20,10 @@ pub struct UserConfig {
     pub voice_type: i64,
     pub generator_type: i64,
     pub read_nickname: Option<String>,
+    pub speed: f64,
+    pub pitch: f64,
+    pub intonation: f64,
+    pub volume: f64,
 }
 impl UserConfig {
     pub fn from_user_id(user_id: i64) -> Self {
@@ -31,6 +34,17 @@ impl UserConfig {
             voice_type: 1,
             generator_type: 0,
             read_nickname: None,
+            speed: 1.0,
+            pitch: 0.0,
+            intonation: 1.0,
+            volume: 1.0,
+        }
+    }
+    pub fn speech_params(&self) -> SpeechParams {
+        SpeechParams {
+            speed: self.speed,
+            pitch: self.pitch,
+            intonation: self.intonation,
         }
     }
 }
@@ -57,6 +71,14 @@ impl UserConfigDB for sqlx::SqlitePool {
         tx.commit().await?;
         Ok(q)
     }
+    async fn get_user_config_all(&self) -> Result<Vec<UserConfig>> {
+        let mut tx = self.begin().await?;
+        let q = query_as!(UserConfig, "SELECT * FROM user_config")
+            .fetch_all(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(q)
+    }
     async fn get_user_config_or_default(&self, user_id: i64) -> Result<UserConfig> {
         match self.get_user_config(user_id).await {
             Err(_) => {
@@ -70,152 +92,879 @@ impl UserConfigDB for sqlx::SqlitePool {
     }
     async fn update_user_config(&self, user_config: &UserConfig) -> Result<u64> {
         let mut tx = self.begin().await?;
-        let q = query!("UPDATE user_config SET hello = ?,bye = ?,voice_type = ?,generator_type = ?,read_nickname = ? WHERE user_id = ?",
-        user_config.hello,user_config.bye,user_config.voice_type,user_config.generator_type,user_config.read_nickname,user_config.user_id)
+        let q = query!("UPDATE user_config SET hello = ?,bye = ?,voice_type = ?,generator_type = ?,read_nickname = ?,speed = ?,pitch = ?,intonation = ?,volume = ? WHERE user_id = ?",
+        user_config.hello,user_config.bye,user_config.voice_type,user_config.generator_type,user_config.read_nickname,user_config.speed,user_config.pitch,user_config.intonation,user_config.volume,user_config.user_id)
         .execute(&mut tx).await?;
         tx.commit().await?;
         Ok(q.rows_affected())
     }
 }
 
+/// 辞書の単語をどう探すか
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    serde::Serialize,
+    serde::Deserialize,
+    poise::ChoiceParameter,
+)]
+#[repr(i64)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// 文字列をそのまま探す
+    #[default]
+    #[name = "literal"]
+    #[name_localized("ja", "そのまま")]
+    Literal = 0,
+    /// 前後が英数字でないところだけを探す
+    #[name = "whole_word"]
+    #[name_localized("ja", "単語単位")]
+    WholeWord = 1,
+    /// 正規表現で探す。読みには $1 や ${name} でキャプチャを使える
+    #[name = "regex"]
+    #[name_localized("ja", "正規表現")]
+    Regex = 2,
+}
+
+/// 辞書の登録先
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DictScope {
+    Global,
+    Guild(i64),
+    User(i64),
+}
+
+impl DictScope {
+    /// dict テーブルでの (guild_id, user_id)。0 は指定なしを表す
+    fn ids(self) -> (i64, i64) {
+        match self {
+            DictScope::Global => (0, 0),
+            DictScope::Guild(guild_id) => (guild_id, 0),
+            DictScope::User(user_id) => (0, user_id),
+        }
+    }
+    fn from_ids(guild_id: i64, user_id: i64) -> Self {
+        match (guild_id, user_id) {
+            (0, 0) => DictScope::Global,
+            (guild_id, 0) => DictScope::Guild(guild_id),
+            (_, user_id) => DictScope::User(user_id),
+        }
+    }
+}
+
+/// 読み上げるときにどの辞書を使うか
+///
+/// 同じ単語が複数の辞書にあるときは 個人 > ギルド > 全体 の順に優先する
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DictContext {
+    pub guild_id: Option<i64>,
+    pub user_id: Option<i64>,
+}
+
+/// 辞書を変更した人
+#[derive(Debug, Clone, Copy)]
+pub struct DictEditor {
+    pub user_id: i64,
+    /// コマンドを使ったギルド。DM なら None
+    pub guild_id: Option<i64>,
+}
+
+/// 辞書の変更履歴
+#[derive(Debug)]
+pub struct DictHistory {
+    pub id: i64,
+    pub word: String,
+    pub author_id: i64,
+    pub origin_guild_id: Option<i64>,
+    /// UNIX 時間 (秒)
+    pub created_at: i64,
+    pub old_read_word: Option<String>,
+    pub old_match_mode: Option<MatchMode>,
+    pub new_read_word: Option<String>,
+    pub new_match_mode: Option<MatchMode>,
+    /// 取り消しのときは取り消した変更の id
+    pub undo_of: Option<i64>,
+    pub undone: bool,
+}
+
+impl DictHistory {
+    fn dict(&self, read_word: &Option<String>, match_mode: Option<MatchMode>) -> Option<Dict> {
+        Some(Dict {
+            word: self.word.clone(),
+            read_word: read_word.clone()?,
+            match_mode: match_mode.unwrap_or_default(),
+        })
+    }
+    /// 変更前の単語。新しく登録されたときは None
+    pub fn before(&self) -> Option<Dict> {
+        self.dict(&self.old_read_word, self.old_match_mode)
+    }
+    /// 変更後の単語。削除されたときは None
+    pub fn after(&self) -> Option<Dict> {
+        self.dict(&self.new_read_word, self.new_match_mode)
+    }
+}
+
 #[async_trait]
 pub trait DictDB {
-    async fn update_dict(&self, dict: &Dict) -> Result<u64>;
-    async fn get_dict(&self, word: &str) -> Result<String>;
-    async fn get_dict_all(&self) -> Result<Vec<Dict>>;
-    async fn remove(&self, word: &str) -> Result<()>;
+    async fn update_dict(&self, scope: DictScope, dict: &Dict, editor: DictEditor) -> Result<u64>;
+    async fn get_dict(&self, scope: DictScope, word: &str) -> Result<Dict>;
+    async fn get_dict_all(&self, scope: DictScope) -> Result<Vec<Dict>>;
+    /// すべての辞書の単語を、どの辞書のものかと一緒に返す
+    async fn get_dict_every_scope(&self) -> Result<Vec<(DictScope, Dict)>>;
+    async fn remove(&self, scope: DictScope, word: &str, editor: DictEditor) -> Result<()>;
+    /// dicts を登録し、removed を削除する。途中で失敗したら何も変更しない
+    async fn import_dict(
+        &self,
+        scope: DictScope,
+        dicts: &[Dict],
+        removed: &[String],
+        editor: DictEditor,
+    ) -> Result<()>;
+    /// word の変更履歴を新しい順に limit 件返す
+    async fn get_dict_history(
+        &self,
+        scope: DictScope,
+        word: &str,
+        limit: i64,
+    ) -> Result<Vec<DictHistory>>;
+    /// word の取り消されていない最新の変更を取り消して、その変更を返す
+    async fn undo_dict(
+        &self,
+        scope: DictScope,
+        word: &str,
+        editor: DictEditor,
+    ) -> Result<DictHistory>;
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+async fn fetch_dict(
+    tx: &mut Transaction<'_, Sqlite>,
+    scope: DictScope,
+    word: &str,
+) -> Result<Option<Dict>> {
+    let (guild_id, user_id) = scope.ids();
+    Ok(query_as!(
+        Dict,
+        r#"SELECT word,read_word,match_mode as "match_mode: MatchMode" FROM dict WHERE word = ? AND guild_id = ? AND user_id = ?"#,
+        word,
+        guild_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?)
+}
+
+/// word を new に変更して履歴に残す。new が None なら削除する。変更がなければ false を返す
+async fn change_dict(
+    tx: &mut Transaction<'_, Sqlite>,
+    scope: DictScope,
+    word: &str,
+    new: Option<&Dict>,
+    editor: DictEditor,
+    undo_of: Option<i64>,
+) -> Result<bool> {
+    let (guild_id, user_id) = scope.ids();
+    let old = fetch_dict(tx, scope, word).await?;
+    if old.as_ref() == new {
+        return Ok(false);
+    }
+    match new {
+        Some(dict) => {
+            let match_mode = dict.match_mode as i64;
+            query!(
+                "INSERT OR REPLACE INTO dict (word,read_word,guild_id,user_id,match_mode) VALUES (?,?,?,?,?)",
+                dict.word,
+                dict.read_word,
+                guild_id,
+                user_id,
+                match_mode
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        None => {
+            query!(
+                "DELETE FROM dict WHERE word = ? AND guild_id = ? AND user_id = ?",
+                word,
+                guild_id,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    let created_at = now();
+    let old_read_word = old.as_ref().map(|dict| &dict.read_word);
+    let old_match_mode = old.as_ref().map(|dict| dict.match_mode as i64);
+    let new_read_word = new.map(|dict| &dict.read_word);
+    let new_match_mode = new.map(|dict| dict.match_mode as i64);
+    query!(
+        "INSERT INTO dict_history (word,guild_id,user_id,author_id,origin_guild_id,created_at,old_read_word,old_match_mode,new_read_word,new_match_mode,undo_of) VALUES (?,?,?,?,?,?,?,?,?,?,?)",
+        word,
+        guild_id,
+        user_id,
+        editor.user_id,
+        editor.guild_id,
+        created_at,
+        old_read_word,
+        old_match_mode,
+        new_read_word,
+        new_match_mode,
+        undo_of
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(true)
 }
 
 #[async_trait]
 impl DictDB for sqlx::SqlitePool {
-    async fn update_dict(&self, dict: &Dict) -> Result<u64> {
+    async fn update_dict(&self, scope: DictScope, dict: &Dict, editor: DictEditor) -> Result<u64> {
         let mut tx = self.begin().await?;
-        let q = query!(
-            "INSERT OR REPLACE INTO dict VALUES (?,?)",
-            dict.word,
-            dict.read_word
-        )
-        .execute(&mut tx)
-        .await
-        .map_or(0, |result| result.rows_affected());
+        let changed = change_dict(&mut tx, scope, &dict.word, Some(dict), editor, None).await?;
         tx.commit().await?;
-        Ok(q)
+        Ok(changed as u64)
     }
-    async fn get_dict(&self, word: &str) -> Result<String> {
+    async fn get_dict(&self, scope: DictScope, word: &str) -> Result<Dict> {
         let mut tx = self.begin().await?;
-        let dict = query!("SELECT read_word FROM dict WHERE word = ?", word)
-            .fetch_optional(&mut tx)
+        let dict = fetch_dict(&mut tx, scope, word)
             .await?
-            .ok_or_else(|| anyhow!("key not found"))?
-            .read_word;
+            .ok_or_else(|| anyhow!("key not found"))?;
         tx.commit().await?;
         Ok(dict)
     }
-    async fn get_dict_all(&self) -> Result<Vec<Dict>> {
+    async fn get_dict_all(&self, scope: DictScope) -> Result<Vec<Dict>> {
+        let (guild_id, user_id) = scope.ids();
         let mut tx = self.begin().await?;
-        let dict = sqlx::query_as!(Dict, "SELECT word,read_word FROM dict")
-            .fetch_all(&mut tx)
-            .await?;
+        let dict = sqlx::query_as!(
+            Dict,
+            r#"SELECT word,read_word,match_mode as "match_mode: MatchMode" FROM dict WHERE guild_id = ? AND user_id = ?"#,
+            guild_id,
+            user_id
+        )
+        .fetch_all(&mut tx)
+        .await?;
         tx.commit().await?;
         Ok(dict)
     }
-    async fn remove(&self, word: &str) -> Result<()> {
-        let mut tx = self.begin().await.unwrap();
-        sqlx::query!("DELETE FROM dict WHERE word = ?", word)
-            .execute(&mut tx)
-            .await?;
+    async fn get_dict_every_scope(&self) -> Result<Vec<(DictScope, Dict)>> {
+        let mut tx = self.begin().await?;
+        let rows = query!(
+            r#"SELECT word,read_word,guild_id,user_id,match_mode as "match_mode: MatchMode" FROM dict"#
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    DictScope::from_ids(row.guild_id, row.user_id),
+                    Dict {
+                        word: row.word,
+                        read_word: row.read_word,
+                        match_mode: row.match_mode,
+                    },
+                )
+            })
+            .collect())
+    }
+    async fn remove(&self, scope: DictScope, word: &str, editor: DictEditor) -> Result<()> {
+        let mut tx = self.begin().await?;
+        if !change_dict(&mut tx, scope, word, None, editor, None).await? {
+            return Err(anyhow!("key not found"));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+    async fn import_dict(
+        &self,
+        scope: DictScope,
+        dicts: &[Dict],
+        removed: &[String],
+        editor: DictEditor,
+    ) -> Result<()> {
+        let mut tx = self.begin().await?;
+        for word in removed {
+            change_dict(&mut tx, scope, word, None, editor, None).await?;
+        }
+        for dict in dicts {
+            change_dict(&mut tx, scope, &dict.word, Some(dict), editor, None).await?;
+        }
         tx.commit().await?;
         Ok(())
     }
+    async fn get_dict_history(
+        &self,
+        scope: DictScope,
+        word: &str,
+        limit: i64,
+    ) -> Result<Vec<DictHistory>> {
+        let (guild_id, user_id) = scope.ids();
+        let mut tx = self.begin().await?;
+        let history = query_as!(
+            DictHistory,
+            r#"SELECT id as "id!",word,author_id,origin_guild_id,created_at,old_read_word,old_match_mode as "old_match_mode: MatchMode",new_read_word,new_match_mode as "new_match_mode: MatchMode",undo_of,undone as "undone: bool" FROM dict_history WHERE word = ? AND guild_id = ? AND user_id = ? ORDER BY id DESC LIMIT ?"#,
+            word,
+            guild_id,
+            user_id,
+            limit
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(history)
+    }
+    async fn undo_dict(
+        &self,
+        scope: DictScope,
+        word: &str,
+        editor: DictEditor,
+    ) -> Result<DictHistory> {
+        let (guild_id, user_id) = scope.ids();
+        let mut tx = self.begin().await?;
+        let history = query_as!(
+            DictHistory,
+            r#"SELECT id as "id!",word,author_id,origin_guild_id,created_at,old_read_word,old_match_mode as "old_match_mode: MatchMode",new_read_word,new_match_mode as "new_match_mode: MatchMode",undo_of,undone as "undone: bool" FROM dict_history WHERE word = ? AND guild_id = ? AND user_id = ? AND undone = 0 AND undo_of IS NULL ORDER BY id DESC LIMIT 1"#,
+            word,
+            guild_id,
+            user_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| anyhow!("history not found"))?;
+        change_dict(
+            &mut tx,
+            scope,
+            word,
+            history.before().as_ref(),
+            editor,
+            Some(history.id),
+        )
+        .await?;
+        query!(
+            "UPDATE dict_history SET undone = 1 WHERE id = ?",
+            history.id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(history)
+    }
 }
 
-pub async fn get_voice_types_by_web_voicevox_api() -> Result<Vec<VoiceType>> {
-    #[derive(Deserialize, Clone, Debug)]
-    struct Style {
-        pub name: String,
-        pub id: u64,
+/// サーバーごとに使える人を設定できるコマンドのまとまり
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type, poise::ChoiceParameter)]
+#[repr(i64)]
+pub enum CommandGroup {
+    /// /add, /dict import, /dict undo
+    #[name = "add"]
+    #[name_localized("ja", "単語の登録")]
+    Add = 0,
+    /// /rem, /dict import, /dict undo
+    #[name = "rem"]
+    #[name_localized("ja", "単語の削除")]
+    Rem = 1,
+    /// /mute, /unmute, /skip, /stop
+    #[name = "mute"]
+    #[name_localized("ja", "ミュート")]
+    Mute = 2,
+    /// /leave
+    #[name = "leave"]
+    #[name_localized("ja", "退出")]
+    Leave = 3,
+    /// /config
+    #[name = "admin"]
+    #[name_localized("ja", "管理者用コマンド")]
+    Admin = 4,
+    /// /ignore
+    #[name = "ignore"]
+    #[name_localized("ja", "読み上げない人の設定")]
+    Ignore = 5,
+}
+
+/// コマンドを使うのに必要な権限かロール。両方あるときはどちらかを持っていれば使える
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionRule {
+    pub guild_id: i64,
+    pub command: CommandGroup,
+    /// Discord の権限のビットフラグ
+    pub permissions: Option<i64>,
+    pub role_id: Option<i64>,
+}
+
+#[async_trait]
+pub trait PermissionDB {
+    /// 設定されていなければ None
+    async fn get_permission_rule(
+        &self,
+        guild_id: i64,
+        command: CommandGroup,
+    ) -> Result<Option<PermissionRule>>;
+    async fn get_permission_rules(&self, guild_id: i64) -> Result<Vec<PermissionRule>>;
+    async fn update_permission_rule(&self, rule: &PermissionRule) -> Result<u64>;
+    async fn remove_permission_rule(&self, guild_id: i64, command: CommandGroup) -> Result<u64>;
+}
+
+#[async_trait]
+impl PermissionDB for sqlx::SqlitePool {
+    async fn get_permission_rule(
+        &self,
+        guild_id: i64,
+        command: CommandGroup,
+    ) -> Result<Option<PermissionRule>> {
+        let command = command as i64;
+        let rule = query_as!(
+            PermissionRule,
+            r#"SELECT guild_id,command as "command: CommandGroup",permissions,role_id FROM permission_rule WHERE guild_id = ? AND command = ?"#,
+            guild_id,
+            command
+        )
+        .fetch_optional(self)
+        .await?;
+        Ok(rule)
     }
-    #[derive(Deserialize, Clone, Debug)]
-    struct Speaker {
-        pub name: String,
-        pub styles: Vec<Style>,
+    async fn get_permission_rules(&self, guild_id: i64) -> Result<Vec<PermissionRule>> {
+        let rules = query_as!(
+            PermissionRule,
+            r#"SELECT guild_id,command as "command: CommandGroup",permissions,role_id FROM permission_rule WHERE guild_id = ? ORDER BY command"#,
+            guild_id
+        )
+        .fetch_all(self)
+        .await?;
+        Ok(rules)
     }
+    async fn update_permission_rule(&self, rule: &PermissionRule) -> Result<u64> {
+        let command = rule.command as i64;
+        let q = query!(
+            "INSERT OR REPLACE INTO permission_rule (guild_id,command,permissions,role_id) VALUES (?,?,?,?)",
+            rule.guild_id,
+            command,
+            rule.permissions,
+            rule.role_id
+        )
+        .execute(self)
+        .await?;
+        Ok(q.rows_affected())
+    }
+    async fn remove_permission_rule(&self, guild_id: i64, command: CommandGroup) -> Result<u64> {
+        let command = command as i64;
+        let q = query!(
+            "DELETE FROM permission_rule WHERE guild_id = ? AND command = ?",
+            guild_id,
+            command
+        )
+        .execute(self)
+        .await?;
+        Ok(q.rows_affected())
+    }
+}
 
-    let mut voice_types = Vec::new();
+/// 読み上げない人の設定
+///
+/// guild_id が 0 の行は /optout で本人が設定したもので、すべてのサーバーで読み上げない
+#[async_trait]
+pub trait ReadFilterDB {
+    /// guild_id のサーバーでの発言を読み上げないか
+    async fn is_ignored(&self, guild_id: i64, user_id: i64) -> Result<bool>;
+    /// 設定が変わったら true
+    async fn set_ignored(&self, guild_id: i64, user_id: i64, ignored: bool) -> Result<bool>;
+    async fn get_ignored_users(&self, guild_id: i64) -> Result<Vec<i64>>;
+    /// bot や webhook の発言を読み上げない設定か
+    async fn get_skip_bots(&self, guild_id: i64) -> Result<bool>;
+    async fn set_skip_bots(&self, guild_id: i64, skip_bots: bool) -> Result<u64>;
+}
 
-    dotenv::dotenv().ok();
-    let speakers: Result<Vec<Speaker>> = async {
-        let api_key = std::env::var("WEB_API_KEY")?;
-        let query_url = "https://api.su-shiki.com/v2/voicevox/speakers/";
-        let client = reqwest::Client::new();
-        let res = client
-            .get(query_url)
-            .query(&[("key", api_key)])
-            .send()
-            .await?;
-        res.json().await.map_err(|e| e.into())
-    }
-    .await;
-    info!("{:?}", &speakers);
-    if let Ok(speakers) = speakers {
-        for speaker in speakers {
-            for style in speaker.styles {
-                voice_types.push(VoiceType {
-                    name: speaker.name.clone(),
-                    style_id: style.id,
-                    style_name: style.name,
-                    generator_type: VOICEVOX,
-                });
-            }
+#[async_trait]
+impl ReadFilterDB for sqlx::SqlitePool {
+    async fn is_ignored(&self, guild_id: i64, user_id: i64) -> Result<bool> {
+        let row = query!(
+            "SELECT user_id FROM read_ignore WHERE guild_id IN (0, ?) AND user_id = ?",
+            guild_id,
+            user_id
+        )
+        .fetch_optional(self)
+        .await?;
+        Ok(row.is_some())
+    }
+    async fn set_ignored(&self, guild_id: i64, user_id: i64, ignored: bool) -> Result<bool> {
+        let q = if ignored {
+            query!(
+                "INSERT OR IGNORE INTO read_ignore (guild_id,user_id) VALUES (?,?)",
+                guild_id,
+                user_id
+            )
+            .execute(self)
+            .await?
+        } else {
+            query!(
+                "DELETE FROM read_ignore WHERE guild_id = ? AND user_id = ?",
+                guild_id,
+                user_id
+            )
+            .execute(self)
+            .await?
+        };
+        Ok(q.rows_affected() > 0)
+    }
+    async fn get_ignored_users(&self, guild_id: i64) -> Result<Vec<i64>> {
+        let rows = query!(
+            "SELECT user_id FROM read_ignore WHERE guild_id = ? ORDER BY user_id",
+            guild_id
+        )
+        .fetch_all(self)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.user_id).collect())
+    }
+    async fn get_skip_bots(&self, guild_id: i64) -> Result<bool> {
+        let row = query!(
+            r#"SELECT skip_bots as "skip_bots: bool" FROM guild_config WHERE guild_id = ?"#,
+            guild_id
+        )
+        .fetch_optional(self)
+        .await?;
+        Ok(row.is_some_and(|row| row.skip_bots))
+    }
+    async fn set_skip_bots(&self, guild_id: i64, skip_bots: bool) -> Result<u64> {
+        let q = query!(
+            "INSERT INTO guild_config (guild_id,skip_bots) VALUES (?,?) ON CONFLICT (guild_id) DO UPDATE SET skip_bots = excluded.skip_bots",
+            guild_id,
+            skip_bots
+        )
+        .execute(self)
+        .await?;
+        Ok(q.rows_affected())
+    }
+}
+
+/// 1 回の発言で読み上げる長さの上限。None なら上限なし
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimit {
+    pub max_chars: Option<usize>,
+    pub max_lines: Option<usize>,
+}
+
+impl ReadLimit {
+    /// 設定していないサーバーで読み上げる文字数
+    pub const DEFAULT_MAX_CHARS: usize = 200;
+
+    /// guild_config の値から作る。NULL は既定値、0 は上限なしを表す
+    fn from_columns(max_chars: Option<i64>, max_lines: Option<i64>) -> Self {
+        let limit = |value: i64| usize::try_from(value).ok().filter(|&value| value > 0);
+        Self {
+            max_chars: match max_chars {
+                None => Some(Self::DEFAULT_MAX_CHARS),
+                Some(max_chars) => limit(max_chars),
+            },
+            max_lines: max_lines.and_then(limit),
         }
     }
+}
+
+impl Default for ReadLimit {
+    fn default() -> Self {
+        Self::from_columns(None, None)
+    }
+}
 
-    Ok(voice_types)
+#[async_trait]
+pub trait ReadLimitDB {
+    async fn get_read_limit(&self, guild_id: i64) -> Result<ReadLimit>;
+    async fn set_read_limit(&self, guild_id: i64, limit: ReadLimit) -> Result<u64>;
 }
 
-pub async fn get_voice_types() -> Result<Vec<VoiceType>> {
-    #[derive(Deserialize, Clone, Debug)]
-    struct Style {
-        pub name: String,
-        pub id: u64,
+#[async_trait]
+impl ReadLimitDB for sqlx::SqlitePool {
+    async fn get_read_limit(&self, guild_id: i64) -> Result<ReadLimit> {
+        let row = query!(
+            "SELECT max_chars,max_lines FROM guild_config WHERE guild_id = ?",
+            guild_id
+        )
+        .fetch_optional(self)
+        .await?;
+        Ok(match row {
+            Some(row) => ReadLimit::from_columns(row.max_chars, row.max_lines),
+            None => ReadLimit::default(),
+        })
     }
-    #[derive(Deserialize, Clone, Debug)]
-    struct Speaker {
-        pub name: String,
-        pub styles: Vec<Style>,
+    async fn set_read_limit(&self, guild_id: i64, limit: ReadLimit) -> Result<u64> {
+        let max_chars = limit.max_chars.unwrap_or(0) as i64;
+        let max_lines = limit.max_lines.unwrap_or(0) as i64;
+        let q = query!(
+            "INSERT INTO guild_config (guild_id,max_chars,max_lines) VALUES (?,?,?) ON CONFLICT (guild_id) DO UPDATE SET max_chars = excluded.max_chars, max_lines = excluded.max_lines",
+            guild_id,
+            max_chars,
+            max_lines
+        )
+        .execute(self)
+        .await?;
+        Ok(q.rows_affected())
     }
-    dotenv::dotenv().ok();
-    let file = std::fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(dotenv::var("GENERATORS")?)?;
-    let generators: Vec<String> = serde_json::from_reader(file)?;
+}
 
-    info!("generators = {:?}", &generators);
-    let mut voice_types = Vec::new();
+// マイグレーション済みのメモリ上のデータベースを返す
+#[cfg(test)]
+pub async fn memory_database() -> sqlx::SqlitePool {
+    let database = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&database).await.unwrap();
+    database
+}
 
-    for (generator_type, url) in generators.iter().enumerate() {
-        let speakers: Result<Vec<Speaker>> = async {
-            let query_url = format!("{}/speakers", url);
-            let client = reqwest::Client::new();
-            let res = client.get(query_url).send().await?;
-            res.json().await.map_err(|e| e.into())
-        }
-        .await;
-        info!("{:?}", &speakers);
-        if let Ok(speakers) = speakers {
-            for speaker in speakers {
-                for style in speaker.styles {
-                    voice_types.push(VoiceType {
-                        name: speaker.name.clone(),
-                        style_id: style.id,
-                        style_name: style.name,
-                        generator_type: generator_type as i64,
-                    });
-                }
-            }
+#[cfg(test)]
+impl Dict {
+    /// 文字列をそのまま探す単語
+    pub fn literal(word: &str, read_word: &str) -> Self {
+        Self::with_mode(word, read_word, MatchMode::Literal)
+    }
+    pub fn with_mode(word: &str, read_word: &str, match_mode: MatchMode) -> Self {
+        Self {
+            word: word.to_string(),
+            read_word: read_word.to_string(),
+            match_mode,
         }
     }
-    Ok(voice_types)
+}
+
+#[cfg(test)]
+pub const TEST_EDITOR: DictEditor = DictEditor {
+    user_id: 100,
+    guild_id: Some(1),
+};
+
+#[tokio::test]
+async fn dict_scope_test() {
+    let database = memory_database().await;
+    database
+        .update_dict(
+            DictScope::Global,
+            &Dict::literal("a", "global"),
+            TEST_EDITOR,
+        )
+        .await
+        .unwrap();
+    database
+        .update_dict(
+            DictScope::Guild(1),
+            &Dict::literal("a", "guild"),
+            TEST_EDITOR,
+        )
+        .await
+        .unwrap();
+    database
+        .update_dict(
+            DictScope::User(3),
+            &Dict::with_mode("b", "user", MatchMode::WholeWord),
+            TEST_EDITOR,
+        )
+        .await
+        .unwrap();
+    let mut rows = database
+        .get_dict_every_scope()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(scope, dict)| (scope, dict.word, dict.read_word))
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|(_, word, read_word)| (word.clone(), read_word.clone()));
+    assert_eq!(
+        rows,
+        vec![
+            (DictScope::Global, "a".to_string(), "global".to_string()),
+            (DictScope::Guild(1), "a".to_string(), "guild".to_string()),
+            (DictScope::User(3), "b".to_string(), "user".to_string()),
+        ]
+    );
+
+    assert_eq!(
+        database
+            .get_dict(DictScope::Guild(1), "a")
+            .await
+            .unwrap()
+            .read_word,
+        "guild"
+    );
+    assert_eq!(
+        database
+            .get_dict(DictScope::User(3), "b")
+            .await
+            .unwrap()
+            .match_mode,
+        MatchMode::WholeWord
+    );
+    assert_eq!(
+        database
+            .get_dict_all(DictScope::Guild(1))
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    database
+        .remove(DictScope::Guild(1), "a", TEST_EDITOR)
+        .await
+        .unwrap();
+    assert!(database
+        .remove(DictScope::Guild(1), "a", TEST_EDITOR)
+        .await
+        .is_err());
+    assert!(database.get_dict(DictScope::Guild(1), "a").await.is_err());
+}
+
+#[tokio::test]
+async fn dict_history_test() {
+    let database = memory_database().await;
+    let scope = DictScope::Guild(1);
+    let dict = |read_word: &str| Dict::literal("nap", read_word);
+    let vandal = DictEditor {
+        user_id: 200,
+        guild_id: Some(1),
+    };
+    database
+        .update_dict(scope, &dict("なっぷ"), TEST_EDITOR)
+        .await
+        .unwrap();
+    // 同じ読みで登録し直しても履歴は増えない
+    database
+        .update_dict(scope, &dict("なっぷ"), TEST_EDITOR)
+        .await
+        .unwrap();
+    database
+        .update_dict(scope, &dict("うんち"), vandal)
+        .await
+        .unwrap();
+    database.remove(scope, "nap", vandal).await.unwrap();
+
+    let history = database.get_dict_history(scope, "nap", 10).await.unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[0].author_id, 200);
+    assert_eq!(history[0].before(), Some(dict("うんち")));
+    assert_eq!(history[0].after(), None);
+    assert_eq!(history[2].before(), None);
+    assert_eq!(history[2].after(), Some(dict("なっぷ")));
+    assert!(database
+        .get_dict_history(DictScope::Global, "nap", 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // 取り消すたびに一つ前の読みに戻る
+    database.undo_dict(scope, "nap", TEST_EDITOR).await.unwrap();
+    assert_eq!(
+        database.get_dict(scope, "nap").await.unwrap(),
+        dict("うんち")
+    );
+    database.undo_dict(scope, "nap", TEST_EDITOR).await.unwrap();
+    assert_eq!(
+        database.get_dict(scope, "nap").await.unwrap(),
+        dict("なっぷ")
+    );
+    database.undo_dict(scope, "nap", TEST_EDITOR).await.unwrap();
+    assert!(database.get_dict(scope, "nap").await.is_err());
+    assert!(database.undo_dict(scope, "nap", TEST_EDITOR).await.is_err());
+
+    let history = database.get_dict_history(scope, "nap", 10).await.unwrap();
+    assert_eq!(history.len(), 6);
+    assert_eq!(history[0].undo_of, Some(history[5].id));
+    assert!(history[5].undone);
+}
+
+#[tokio::test]
+async fn permission_rule_test() {
+    let database = memory_database().await;
+    let rule = PermissionRule {
+        guild_id: 1,
+        command: CommandGroup::Add,
+        permissions: Some(0x20),
+        role_id: None,
+    };
+    database.update_permission_rule(&rule).await.unwrap();
+    let changed = PermissionRule {
+        role_id: Some(10),
+        ..rule.clone()
+    };
+    database.update_permission_rule(&changed).await.unwrap();
+    assert_eq!(
+        database
+            .get_permission_rule(1, CommandGroup::Add)
+            .await
+            .unwrap(),
+        Some(changed)
+    );
+    assert_eq!(
+        database
+            .get_permission_rule(2, CommandGroup::Add)
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(database.get_permission_rules(1).await.unwrap().len(), 1);
+    assert_eq!(
+        database
+            .remove_permission_rule(1, CommandGroup::Add)
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(database.get_permission_rules(1).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn read_filter_test() {
+    let database = memory_database().await;
+    assert!(database.set_ignored(1, 10, true).await.unwrap());
+    assert!(!database.set_ignored(1, 10, true).await.unwrap());
+    assert!(database.is_ignored(1, 10).await.unwrap());
+    assert!(!database.is_ignored(2, 10).await.unwrap());
+    // /optout はどのサーバーでも効く
+    database.set_ignored(0, 20, true).await.unwrap();
+    assert!(database.is_ignored(2, 20).await.unwrap());
+    assert_eq!(database.get_ignored_users(1).await.unwrap(), vec![10]);
+    assert!(database.set_ignored(1, 10, false).await.unwrap());
+    assert!(!database.is_ignored(1, 10).await.unwrap());
+
+    assert!(!database.get_skip_bots(1).await.unwrap());
+    database.set_skip_bots(1, true).await.unwrap();
+    assert!(database.get_skip_bots(1).await.unwrap());
+    database.set_skip_bots(1, false).await.unwrap();
+    assert!(!database.get_skip_bots(1).await.unwrap());
+}
+
+#[tokio::test]
+async fn read_limit_test() {
+    let database = memory_database().await;
+    assert_eq!(
+        database.get_read_limit(1).await.unwrap(),
+        ReadLimit {
+            max_chars: Some(ReadLimit::DEFAULT_MAX_CHARS),
+            max_lines: None,
+        }
+    );
+    // bot を読まない設定をしても長さの上限は既定値のまま
+    database.set_skip_bots(1, true).await.unwrap();
+    assert_eq!(
+        database.get_read_limit(1).await.unwrap(),
+        ReadLimit::default()
+    );
+    let limit = ReadLimit {
+        max_chars: None,
+        max_lines: Some(3),
+    };
+    database.set_read_limit(1, limit).await.unwrap();
+    assert_eq!(database.get_read_limit(1).await.unwrap(), limit);
+    assert!(database.get_skip_bots(1).await.unwrap());
 }
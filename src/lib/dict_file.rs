@@ -0,0 +1,437 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use wana_kana::{is_kana::is_kana, to_katakana::to_katakana};
+
+use super::db::MatchMode;
+use crate::Dict;
+
+/// /dict export で出力する形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum DictFormat {
+    #[name = "json"]
+    Json,
+    #[name = "csv"]
+    Csv,
+    /// VOICEVOX エンジンの /user_dict と同じ形式
+    #[name = "voicevox"]
+    VoiceVox,
+}
+
+impl DictFormat {
+    pub fn filename(self) -> &'static str {
+        match self {
+            DictFormat::Json => "dict.json",
+            DictFormat::Csv => "dict.csv",
+            DictFormat::VoiceVox => "user_dict.json",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    word: String,
+    read_word: String,
+    #[serde(default)]
+    match_mode: MatchMode,
+}
+
+/// VOICEVOX エンジンのユーザー辞書の単語
+///
+/// 読み込むときは surface と pronunciation だけを使う
+#[derive(Serialize, Deserialize)]
+struct VoiceVoxWord {
+    surface: String,
+    pronunciation: String,
+    #[serde(default)]
+    priority: u32,
+    #[serde(default)]
+    accent_type: u32,
+    #[serde(default, skip_deserializing)]
+    mora_count: Option<u32>,
+    #[serde(default, skip_deserializing)]
+    context_id: u32,
+    #[serde(default, skip_deserializing)]
+    part_of_speech: String,
+    #[serde(default, skip_deserializing)]
+    part_of_speech_detail_1: String,
+    #[serde(default, skip_deserializing)]
+    part_of_speech_detail_2: String,
+    #[serde(default, skip_deserializing)]
+    part_of_speech_detail_3: String,
+    #[serde(default, skip_deserializing)]
+    inflectional_type: String,
+    #[serde(default, skip_deserializing)]
+    inflectional_form: String,
+    #[serde(default, skip_deserializing)]
+    stem: String,
+    #[serde(default, skip_deserializing)]
+    yomi: String,
+    #[serde(default, skip_deserializing)]
+    accent_associative_rule: String,
+}
+
+impl VoiceVoxWord {
+    /// VOICEVOX エンジンが固有名詞を登録したときと同じ値で埋める
+    fn new(surface: String, pronunciation: String) -> Self {
+        Self {
+            surface,
+            yomi: pronunciation.clone(),
+            pronunciation,
+            priority: 5,
+            accent_type: 0,
+            mora_count: None,
+            context_id: 1348,
+            part_of_speech: "名詞".to_string(),
+            part_of_speech_detail_1: "固有名詞".to_string(),
+            part_of_speech_detail_2: "一般".to_string(),
+            part_of_speech_detail_3: "*".to_string(),
+            inflectional_type: "*".to_string(),
+            inflectional_form: "*".to_string(),
+            stem: "*".to_string(),
+            accent_associative_rule: "*".to_string(),
+        }
+    }
+}
+
+/// VOICEVOX の surface は全角に変換されているので半角に戻す
+fn to_hankaku(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            '\u{3000}' => ' ',
+            _ => c,
+        })
+        .collect()
+}
+
+fn to_zenkaku(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '!'..='~' => char::from_u32(c as u32 + 0xFEE0).unwrap_or(c),
+            ' ' => '\u{3000}',
+            _ => c,
+        })
+        .collect()
+}
+
+/// ファイル名の拡張子と中身から形式を判断して読み込む
+///
+/// JSON はこの bot の形式 (配列) と VOICEVOX の形式 (UUID をキーにしたオブジェクト) のどちらも読める
+pub fn parse(filename: &str, content: &str) -> Result<Vec<Dict>> {
+    if filename.to_lowercase().ends_with(".csv") {
+        return parse_csv(content);
+    }
+    let value: serde_json::Value =
+        serde_json::from_str(content).map_err(|_| anyhow!("JSON か CSV のファイルを選んでね"))?;
+    if value.is_array() {
+        let entries: Vec<JsonEntry> = serde_json::from_value(value)
+            .map_err(|e| anyhow!("辞書のファイルとして読めなかったよ: {}", e))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| Dict {
+                word: entry.word,
+                read_word: entry.read_word,
+                match_mode: entry.match_mode,
+            })
+            .collect())
+    } else {
+        let words: BTreeMap<String, VoiceVoxWord> = serde_json::from_value(value)
+            .map_err(|e| anyhow!("VOICEVOX の辞書として読めなかったよ: {}", e))?;
+        Ok(words
+            .into_values()
+            .map(|word| Dict {
+                word: to_hankaku(&word.surface),
+                read_word: word.pronunciation,
+                match_mode: MatchMode::Literal,
+            })
+            .collect())
+    }
+}
+
+/// word,read_word[,match_mode] の CSV を読み込む。1 行目が word から始まっていれば見出しとして飛ばす
+fn parse_csv(content: &str) -> Result<Vec<Dict>> {
+    let mut dicts = Vec::new();
+    for (i, mut record) in csv_records(content)?.into_iter().enumerate() {
+        if i == 0 && record.first().is_some_and(|field| field == "word") {
+            continue;
+        }
+        let match_mode = match record.len() {
+            2 => MatchMode::Literal,
+            3 => record
+                .pop()
+                .unwrap()
+                .parse()
+                .map_err(|_| anyhow!("CSV の {} 行目の match_mode が正しくないよ", i + 1))?,
+            _ => return Err(anyhow!("CSV の {} 行目が 2 列か 3 列になってないよ", i + 1)),
+        };
+        let read_word = record.pop().unwrap();
+        let word = record.pop().unwrap();
+        dicts.push(Dict {
+            word,
+            read_word,
+            match_mode,
+        });
+    }
+    Ok(dicts)
+}
+
+fn csv_records(content: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = content.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') => (),
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("CSV の \" が閉じられてないよ"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records
+        .into_iter()
+        .filter(|record| record != &[""])
+        .collect())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// 書き出したファイルの中身と、形式の都合で書き出せなかった単語の数を返す
+///
+/// VOICEVOX の辞書は文字列をそのまま探す単語だけで、読みがカタカナでなければならないので、
+/// それ以外の単語は書き出さない
+pub fn export(format: DictFormat, dicts: &[Dict]) -> Result<(String, usize)> {
+    match format {
+        DictFormat::Json => {
+            let entries = dicts
+                .iter()
+                .map(|dict| JsonEntry {
+                    word: dict.word.clone(),
+                    read_word: dict.read_word.clone(),
+                    match_mode: dict.match_mode,
+                })
+                .collect::<Vec<_>>();
+            Ok((serde_json::to_string_pretty(&entries)?, 0))
+        }
+        DictFormat::Csv => {
+            let mut csv = "word,read_word,match_mode\n".to_string();
+            for dict in dicts {
+                csv.push_str(&format!(
+                    "{},{},{}\n",
+                    csv_field(&dict.word),
+                    csv_field(&dict.read_word),
+                    dict.match_mode.name()
+                ));
+            }
+            Ok((csv, 0))
+        }
+        DictFormat::VoiceVox => {
+            let words = dicts
+                .iter()
+                .filter(|dict| dict.match_mode == MatchMode::Literal && is_kana(&dict.read_word))
+                .map(|dict| {
+                    (
+                        uuid::Uuid::new_v4().to_string(),
+                        VoiceVoxWord::new(to_zenkaku(&dict.word), to_katakana(&dict.read_word)),
+                    )
+                })
+                .collect::<BTreeMap<_, _>>();
+            let skipped = dicts.len() - words.len();
+            Ok((serde_json::to_string_pretty(&words)?, skipped))
+        }
+    }
+}
+
+/// 取り込んだときに辞書がどう変わるか
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    /// 新しく追加される単語
+    pub added: Vec<Dict>,
+    /// 読みか探し方が変わる単語。(今の単語, 新しい単語)
+    pub changed: Vec<(Dict, Dict)>,
+    /// 同じ読みと探し方で登録済みの単語の数
+    pub unchanged: usize,
+    /// replace のときに消える単語
+    pub removed: Vec<String>,
+}
+
+impl ImportPlan {
+    /// existing に incoming を取り込んだときの変化を求める。incoming に同じ単語があれば後のものを使う
+    pub fn new(existing: &[Dict], incoming: &[Dict], replace: bool) -> Self {
+        let current = existing
+            .iter()
+            .map(|dict| (dict.word.as_str(), dict))
+            .collect::<HashMap<_, _>>();
+        let mut incoming_map = BTreeMap::new();
+        for dict in incoming {
+            incoming_map.insert(dict.word.as_str(), dict);
+        }
+        let mut plan = ImportPlan::default();
+        for (word, dict) in incoming_map.iter() {
+            match current.get(word) {
+                None => plan.added.push((*dict).clone()),
+                Some(old) if old == dict => plan.unchanged += 1,
+                Some(old) => plan.changed.push(((*old).clone(), (*dict).clone())),
+            }
+        }
+        if replace {
+            plan.removed = existing
+                .iter()
+                .filter(|dict| !incoming_map.contains_key(dict.word.as_str()))
+                .map(|dict| dict.word.clone())
+                .collect();
+            plan.removed.sort();
+        }
+        plan
+    }
+    /// 書き込む単語の一覧
+    pub fn writes(&self) -> Vec<Dict> {
+        self.added
+            .iter()
+            .chain(self.changed.iter().map(|(_, new)| new))
+            .cloned()
+            .collect()
+    }
+}
+
+#[test]
+fn csv_test() {
+    let dicts = vec![
+        Dict::literal("nap", "なっぷ"),
+        Dict::literal("a,b", "えー\"びー\""),
+        Dict::literal("改\n行", "かいぎょう"),
+        Dict::with_mode(r"(\d+)w", "$1わっと", MatchMode::Regex),
+    ];
+    let (csv, skipped) = export(DictFormat::Csv, &dicts).unwrap();
+    assert_eq!(skipped, 0);
+    assert_eq!(parse("dict.csv", &csv).unwrap(), dicts);
+    assert_eq!(
+        parse("dict.CSV", "nap,なっぷ\r\nai,えーあい,whole_word").unwrap(),
+        vec![
+            Dict::literal("nap", "なっぷ"),
+            Dict::with_mode("ai", "えーあい", MatchMode::WholeWord)
+        ]
+    );
+    assert!(parse("dict.csv", "nap\n").is_err());
+    assert!(parse("dict.csv", "nap,なっぷ,unknown\n").is_err());
+    assert!(parse("dict.csv", "\"nap,なっぷ\n").is_err());
+}
+
+#[test]
+fn json_test() {
+    let dicts = vec![
+        Dict::literal("nap", "なっぷ"),
+        Dict::with_mode("bot", "ぼっと", MatchMode::WholeWord),
+    ];
+    let (json, _) = export(DictFormat::Json, &dicts).unwrap();
+    assert_eq!(parse("dict.json", &json).unwrap(), dicts);
+    // match_mode がなければ文字列をそのまま探す
+    assert_eq!(
+        parse("dict.json", r#"[{"word": "nap", "read_word": "なっぷ"}]"#).unwrap(),
+        vec![Dict::literal("nap", "なっぷ")]
+    );
+    assert!(parse("dict.json", "nap,なっぷ").is_err());
+}
+
+#[test]
+fn voicevox_test() {
+    let json = r#"{
+        "7c2b4a2e-5e3d-4b4b-9d3a-0f1e2d3c4b5a": {
+            "surface": "ｎａｐ",
+            "priority": 5,
+            "context_id": 1348,
+            "part_of_speech": "名詞",
+            "part_of_speech_detail_1": "固有名詞",
+            "part_of_speech_detail_2": "一般",
+            "part_of_speech_detail_3": "*",
+            "inflectional_type": "*",
+            "inflectional_form": "*",
+            "stem": "*",
+            "yomi": "ナップ",
+            "pronunciation": "ナップ",
+            "accent_type": 1,
+            "mora_count": 3,
+            "accent_associative_rule": "*"
+        }
+    }"#;
+    assert_eq!(
+        parse("user_dict.json", json).unwrap(),
+        vec![Dict::literal("nap", "ナップ")]
+    );
+
+    // かな以外を含む読みや、文字列をそのまま探さない単語は VOICEVOX の辞書に書き出せない
+    let dicts = vec![
+        Dict::literal("nap", "なっぷ"),
+        Dict::literal("www", "わら2"),
+        Dict::with_mode("bot", "ぼっと", MatchMode::WholeWord),
+    ];
+    let (json, skipped) = export(DictFormat::VoiceVox, &dicts).unwrap();
+    assert_eq!(skipped, 2);
+    assert_eq!(
+        parse("user_dict.json", &json).unwrap(),
+        vec![Dict::literal("nap", "ナップ")]
+    );
+    assert!(json.contains("ｎａｐ"));
+}
+
+#[test]
+fn import_plan_test() {
+    let existing = vec![
+        Dict::literal("nap", "なっぷ"),
+        Dict::literal("bot", "ぼっと"),
+        Dict::literal("ai", "えーあい"),
+        Dict::literal("old", "おーるど"),
+    ];
+    let whole_word_ai = Dict::with_mode("ai", "えーあい", MatchMode::WholeWord);
+    let incoming = vec![
+        Dict::literal("nap", "なっぷ"),
+        Dict::literal("bot", "ろぼっと"),
+        whole_word_ai.clone(),
+        Dict::literal("new", "にゅー"),
+    ];
+    let plan = ImportPlan::new(&existing, &incoming, false);
+    assert_eq!(plan.added, vec![Dict::literal("new", "にゅー")]);
+    // 読みが同じでも探し方が変われば変更になる
+    assert_eq!(
+        plan.changed,
+        vec![
+            (Dict::literal("ai", "えーあい"), whole_word_ai),
+            (
+                Dict::literal("bot", "ぼっと"),
+                Dict::literal("bot", "ろぼっと")
+            ),
+        ]
+    );
+    assert_eq!(plan.unchanged, 1);
+    assert!(plan.removed.is_empty());
+    assert_eq!(plan.writes().len(), 3);
+
+    let plan = ImportPlan::new(&existing, &incoming, true);
+    assert_eq!(plan.removed, vec!["old".to_string()]);
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use anyhow::{anyhow, Result};
+use regex::{CaptureLocations, Regex, RegexBuilder};
+use tracing::info;
+
+use super::db::MatchMode;
+use crate::Dict;
+
+/// 辞書に登録する正規表現のサイズの上限
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// 単語単位の単語をまとめた正規表現のサイズの上限
+const WHOLE_WORD_SIZE_LIMIT: usize = 1 << 26;
+
+fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// 辞書に登録できる単語か確かめる
+pub fn validate(dict: &Dict) -> Result<()> {
+    if dict.word.is_empty() {
+        return Err(anyhow!("空の単語は登録できないよ"));
+    }
+    if dict.match_mode == MatchMode::Regex {
+        let regex = build_regex(&dict.word)
+            .map_err(|e| anyhow!("{} は正規表現として正しくないよ\n{}", dict.word, e))?;
+        if regex.is_match("") {
+            return Err(anyhow!(
+                "{} は空の文字列にマッチするから登録できないよ",
+                dict.word
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_ascii_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// 前後が英数字でないところだけにマッチする正規表現
+///
+/// 英数字以外で始まる (終わる) 単語は、その側の境界を気にしない
+fn whole_word_pattern(word: &str) -> String {
+    let boundary = |c: Option<char>| {
+        if c.is_some_and(is_ascii_word) {
+            r"(?-u:\b)"
+        } else {
+            ""
+        }
+    };
+    format!(
+        "{}{}{}",
+        boundary(word.chars().next()),
+        regex::escape(word),
+        boundary(word.chars().last())
+    )
+}
+
+/// 読みの中の $1, ${1}, $name, ${name} をキャプチャした文字列に置き換える。$$ は $ になる
+fn expand(template: &str, text: &str, regex: &Regex, locs: &CaptureLocations) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = r;
+            continue;
+        }
+        let (name, r) = match rest.strip_prefix('{') {
+            Some(r) => match r.find('}') {
+                Some(j) => (&r[..j], &r[j + 1..]),
+                None => {
+                    expanded.push('$');
+                    continue;
+                }
+            },
+            None => {
+                let j = rest.find(|c: char| !is_ascii_word(c)).unwrap_or(rest.len());
+                if j == 0 {
+                    expanded.push('$');
+                    continue;
+                }
+                (&rest[..j], &rest[j..])
+            }
+        };
+        rest = r;
+        let index = name
+            .parse::<usize>()
+            .ok()
+            .or_else(|| regex.capture_names().position(|n| n == Some(name)));
+        if let Some((start, end)) = index.and_then(|i| locs.get(i)) {
+            expanded.push_str(&text[start..end]);
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[derive(Debug)]
+struct RegexEntry {
+    regex: Regex,
+    read_word: String,
+}
+
+#[derive(Clone)]
+struct Found {
+    start: usize,
+    end: usize,
+    replacement: String,
+}
+
+/// 辞書の単語を一度に置き換える
+///
+/// 一番左で見つかった単語を、同じ位置から始まる単語が複数あれば長いものを優先して置き換え、
+/// 置き換えた後の文字列はもう一度置き換えない
+#[derive(Debug)]
+pub struct DictMatcher {
+    literal: Option<AhoCorasick>,
+    literal_read_words: Vec<String>,
+    whole_word: Option<Regex>,
+    whole_word_read_words: HashMap<String, String>,
+    regexes: Vec<RegexEntry>,
+}
+
+impl DictMatcher {
+    /// 正しくない単語は読み飛ばす
+    pub fn new(dicts: &[Dict]) -> Self {
+        let mut literals = Vec::new();
+        let mut whole_words = Vec::new();
+        let mut regexes = Vec::new();
+        for dict in dicts {
+            if let Err(e) = validate(dict) {
+                info!("skip dictionary entry: {}", e);
+                continue;
+            }
+            match dict.match_mode {
+                MatchMode::Literal => literals.push(dict),
+                MatchMode::WholeWord => whole_words.push(dict),
+                MatchMode::Regex => regexes.push(RegexEntry {
+                    regex: build_regex(&dict.word).unwrap(),
+                    read_word: dict.read_word.clone(),
+                }),
+            }
+        }
+        let literal = (!literals.is_empty()).then(|| {
+            AhoCorasickBuilder::new()
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(literals.iter().map(|dict| &dict.word))
+        });
+        // 同じ位置から始まるときに長い単語が優先されるように、長い順に並べてつなげる
+        whole_words.sort_by_key(|dict| std::cmp::Reverse(dict.word.len()));
+        let whole_word = if whole_words.is_empty() {
+            None
+        } else {
+            let pattern = whole_words
+                .iter()
+                .map(|dict| whole_word_pattern(&dict.word))
+                .collect::<Vec<_>>()
+                .join("|");
+            RegexBuilder::new(&pattern)
+                .size_limit(WHOLE_WORD_SIZE_LIMIT)
+                .build()
+                .map_err(|e| info!("skip whole word entries: {}", e))
+                .ok()
+        };
+        Self {
+            literal,
+            literal_read_words: literals.iter().map(|dict| dict.read_word.clone()).collect(),
+            whole_word,
+            whole_word_read_words: whole_words
+                .iter()
+                .map(|dict| (dict.word.clone(), dict.read_word.clone()))
+                .collect(),
+            regexes,
+        }
+    }
+    /// source 番目の探し方で text の pos 以降で最初に見つかるものを探す
+    ///
+    /// 0 番目は文字列そのまま、1 番目は単語単位、2 番目以降は正規表現
+    fn find(&self, source: usize, text: &str, pos: usize) -> Option<Found> {
+        match source {
+            0 => {
+                let m = self.literal.as_ref()?.find(&text[pos..])?;
+                Some(Found {
+                    start: pos + m.start(),
+                    end: pos + m.end(),
+                    replacement: self.literal_read_words[m.pattern()].clone(),
+                })
+            }
+            1 => {
+                let m = self.whole_word.as_ref()?.find_at(text, pos)?;
+                Some(Found {
+                    start: m.start(),
+                    end: m.end(),
+                    replacement: self.whole_word_read_words.get(m.as_str())?.clone(),
+                })
+            }
+            _ => {
+                let entry = self.regexes.get(source - 2)?;
+                let mut locs = entry.regex.capture_locations();
+                let mut from = pos;
+                loop {
+                    let m = entry.regex.captures_read_at(&mut locs, text, from)?;
+                    if m.start() < m.end() {
+                        return Some(Found {
+                            start: m.start(),
+                            end: m.end(),
+                            replacement: expand(&entry.read_word, text, &entry.regex, &locs),
+                        });
+                    }
+                    // 空の文字列へのマッチは置き換えない
+                    from = m.start() + text[m.start()..].chars().next()?.len_utf8();
+                }
+            }
+        }
+    }
+    pub fn replace(&self, text: &str) -> String {
+        let sources = 2 + self.regexes.len();
+        // 探し方ごとに、前回見つかったもの。pos より前から始まるものは探し直す
+        let mut found: Vec<Option<Option<Found>>> = vec![None; sources];
+        let mut replaced = String::new();
+        let mut pos = 0;
+        loop {
+            let mut best: Option<Found> = None;
+            for (source, cached) in found.iter_mut().enumerate() {
+                if cached
+                    .as_ref()
+                    .is_some_and(|f| f.as_ref().is_some_and(|f| f.start < pos))
+                {
+                    *cached = None;
+                }
+                let candidate = cached.get_or_insert_with(|| self.find(source, text, pos));
+                if let Some(candidate) = candidate {
+                    let better = best.as_ref().is_none_or(|best| {
+                        (candidate.start, best.end) < (best.start, candidate.end)
+                    });
+                    if better {
+                        best = Some(candidate.clone());
+                    }
+                }
+            }
+            match best {
+                Some(best) => {
+                    replaced.push_str(&text[pos..best.start]);
+                    replaced.push_str(&best.replacement);
+                    pos = best.end;
+                }
+                None => break,
+            }
+        }
+        replaced.push_str(&text[pos..]);
+        replaced
+    }
+}
+
+#[cfg(test)]
+fn matcher(dicts: &[(&str, &str, MatchMode)]) -> DictMatcher {
+    let dicts = dicts
+        .iter()
+        .map(|(word, read_word, match_mode)| Dict::with_mode(word, read_word, *match_mode))
+        .collect::<Vec<_>>();
+    DictMatcher::new(&dicts)
+}
+
+#[cfg(test)]
+fn literal(dicts: &[(&str, &str)]) -> DictMatcher {
+    matcher(
+        &dicts
+            .iter()
+            .map(|(word, read_word)| (*word, *read_word, MatchMode::Literal))
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[test]
+fn overlap_test() {
+    // 登録した順番によらず長い単語が優先される
+    let expected = "なっぷちゃんとなっぷ";
+    let a = literal(&[("nap", "なっぷ"), ("nap-chan", "なっぷちゃん")]);
+    let b = literal(&[("nap-chan", "なっぷちゃん"), ("nap", "なっぷ")]);
+    assert_eq!(a.replace("nap-chanとnap"), expected);
+    assert_eq!(b.replace("nap-chanとnap"), expected);
+
+    // 重なっているときは左から先に見つかったものが優先される
+    let m = literal(&[("abc", "1"), ("cde", "2")]);
+    assert_eq!(m.replace("abcde"), "1de");
+}
+
+#[test]
+fn chained_test() {
+    // 置き換えた結果はもう一度置き換えない
+    let m = literal(&[("a", "b"), ("b", "c")]);
+    assert_eq!(m.replace("ab"), "bc");
+    let m = literal(&[("草", "くさ"), ("くさ", "におい")]);
+    assert_eq!(m.replace("草"), "くさ");
+}
+
+#[test]
+fn empty_test() {
+    let m = literal(&[]);
+    assert_eq!(m.replace("nap"), "nap");
+    let m = literal(&[("", "から"), ("nap", "なっぷ")]);
+    assert_eq!(m.replace("nap"), "なっぷ");
+}
+
+#[test]
+fn whole_word_test() {
+    let m = matcher(&[
+        ("ai", "えーあい", MatchMode::WholeWord),
+        ("ai chan", "あいちゃん", MatchMode::WholeWord),
+        ("C++", "しーぷらぷら", MatchMode::WholeWord),
+    ]);
+    assert_eq!(m.replace("mail said"), "mail said");
+    assert_eq!(m.replace("ai, aiが好き"), "えーあい, えーあいが好き");
+    assert_eq!(m.replace("ai chan"), "あいちゃん");
+    assert_eq!(m.replace("ai_chan"), "ai_chan");
+    // 英数字以外で終わる単語は後ろの境界を気にしない
+    assert_eq!(m.replace("C++20 と ObjC++"), "しーぷらぷら20 と ObjC++");
+}
+
+#[test]
+fn regex_test() {
+    let m = matcher(&[
+        (r"(\d+)w", "$1ワット", MatchMode::Regex),
+        (r"(?P<n>\d+)円", "${n}えん$$", MatchMode::Regex),
+        ("w+", "わら", MatchMode::Regex),
+    ]);
+    assert_eq!(m.replace("100w"), "100ワット");
+    assert_eq!(m.replace("5円"), "5えん$");
+    assert_eq!(m.replace("www 1w"), "わら 1ワット");
+
+    // 種類が違っても一番左で一番長いものが優先される。同じ長さなら 文字列そのまま > 単語単位 > 正規表現
+    let m = matcher(&[
+        ("nap", "なっぷ", MatchMode::Literal),
+        ("nap-?chan", "なっぷちゃん", MatchMode::Regex),
+        ("napchan", "ちゃん", MatchMode::WholeWord),
+    ]);
+    assert_eq!(
+        m.replace("napchan nap-chan nap"),
+        "ちゃん なっぷちゃん なっぷ"
+    );
+}
+
+#[test]
+fn validate_test() {
+    let dict = |word: &str, match_mode| Dict::with_mode(word, "よみ", match_mode);
+    assert!(validate(&dict("(", MatchMode::Literal)).is_ok());
+    assert!(validate(&dict("(", MatchMode::Regex)).is_err());
+    assert!(validate(&dict("a*", MatchMode::Regex)).is_err());
+    assert!(validate(&dict("a+", MatchMode::Regex)).is_ok());
+    assert!(validate(&dict("", MatchMode::WholeWord)).is_err());
+}
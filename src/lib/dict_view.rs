@@ -0,0 +1,329 @@
+use poise::serenity_prelude::{ButtonStyle, CreateComponents, CreateEmbed};
+
+use super::db::{DictHistory, DictScope, MatchMode};
+use crate::Dict;
+
+/// /dict list で表示するボタンの custom_id の接頭辞
+pub const DICT_LIST_MENU: &str = "dict_list";
+
+/// embed 1 ページに表示する単語の数
+const PAGE_SIZE: usize = 20;
+
+/// /dict history で表示する履歴の数
+pub const HISTORY_SIZE: usize = 10;
+
+/// embed に表示するときの単語や読みの最大文字数
+const MAX_DISPLAY_LEN: usize = 50;
+
+/// Discord で 1 つのメッセージに送れる文字数
+const MAX_MESSAGE_LEN: usize = 2000;
+
+/// custom_id から表示する辞書とページを取り出す
+///
+/// custom_id は dict_list:<scope>:<page> の形で、scope は global, guild:ID, user:ID のいずれか
+pub fn parse_action(custom_id: &str) -> Option<(DictScope, usize)> {
+    let rest = custom_id.strip_prefix(DICT_LIST_MENU)?.strip_prefix(':')?;
+    let (scope, page) = rest.rsplit_once(':')?;
+    let page = page.parse().ok()?;
+    let scope = match scope.split_once(':') {
+        None if scope == "global" => DictScope::Global,
+        Some(("guild", id)) => DictScope::Guild(id.parse().ok()?),
+        Some(("user", id)) => DictScope::User(id.parse().ok()?),
+        _ => return None,
+    };
+    Some((scope, page))
+}
+
+fn scope_key(scope: DictScope) -> String {
+    match scope {
+        DictScope::Global => "global".to_string(),
+        DictScope::Guild(guild_id) => format!("guild:{}", guild_id),
+        DictScope::User(user_id) => format!("user:{}", user_id),
+    }
+}
+
+pub fn scope_name(scope: DictScope) -> &'static str {
+    match scope {
+        DictScope::Global => "全体の辞書",
+        DictScope::Guild(_) => "このサーバーの辞書",
+        DictScope::User(_) => "個人の辞書",
+    }
+}
+
+pub fn ellipsize(text: &str) -> String {
+    if text.chars().count() > MAX_DISPLAY_LEN {
+        format!(
+            "{}…",
+            text.chars().take(MAX_DISPLAY_LEN).collect::<String>()
+        )
+    } else {
+        text.to_string()
+    }
+}
+
+/// 単語 → 読み の形で表示する。文字列をそのまま探す単語以外は探し方もつける
+pub fn describe(dict: &Dict) -> String {
+    let mut text = format!("{} → {}", ellipsize(&dict.word), ellipsize(&dict.read_word));
+    if dict.match_mode != MatchMode::Literal {
+        text.push_str(&format!(
+            " ({})",
+            dict.match_mode
+                .localized_name("ja")
+                .unwrap_or_else(|| dict.match_mode.name())
+        ));
+    }
+    text
+}
+
+fn dict_lines<'a>(dicts: impl Iterator<Item = &'a Dict>) -> String {
+    dicts.map(describe).collect::<Vec<_>>().join("\n")
+}
+
+fn page_count(len: usize) -> usize {
+    len.div_ceil(PAGE_SIZE).max(1)
+}
+
+pub struct DictListView {
+    pub embed: CreateEmbed,
+    pub components: CreateComponents,
+}
+
+/// 辞書の page ページ目を表示する。dicts は単語順に並べ替えてから表示する
+pub fn list_page(scope: DictScope, mut dicts: Vec<Dict>, page: usize) -> DictListView {
+    dicts.sort_by(|a, b| a.word.cmp(&b.word));
+    let pages = page_count(dicts.len());
+    let page = page.min(pages - 1);
+    let mut embed = CreateEmbed::default();
+    embed.title(scope_name(scope));
+    if dicts.is_empty() {
+        embed.description("登録されている単語はないよ");
+    } else {
+        embed.description(dict_lines(
+            dicts.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE),
+        ));
+    }
+    embed.footer(|footer| {
+        footer.text(format!(
+            "{}/{} ページ ({} 件)",
+            page + 1,
+            pages,
+            dicts.len()
+        ))
+    });
+    let mut components = CreateComponents::default();
+    if pages > 1 {
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .custom_id(format!(
+                        "{}:{}:{}",
+                        DICT_LIST_MENU,
+                        scope_key(scope),
+                        page.saturating_sub(1)
+                    ))
+                    .label("前へ")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0)
+            })
+            .create_button(|button| {
+                button
+                    .custom_id(format!(
+                        "{}:{}:{}",
+                        DICT_LIST_MENU,
+                        scope_key(scope),
+                        page + 1
+                    ))
+                    .label("次へ")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 >= pages)
+            })
+        });
+    }
+    DictListView { embed, components }
+}
+
+/// 単語か読みに query を含むものを検索する
+pub fn search(dicts: Vec<Dict>, query: &str) -> Vec<Dict> {
+    let mut found = dicts
+        .into_iter()
+        .filter(|dict| dict.word.contains(query) || dict.read_word.contains(query))
+        .collect::<Vec<_>>();
+    found.sort_by(|a, b| a.word.cmp(&b.word));
+    found
+}
+
+/// 検索結果を表示する。多すぎるときは先頭の 1 ページ分だけ表示する
+pub fn search_result(scope: DictScope, query: &str, found: &[Dict]) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.title(format!(
+        "{} で「{}」を検索",
+        scope_name(scope),
+        ellipsize(query)
+    ));
+    if found.is_empty() {
+        embed.description("見つからなかったよ");
+    } else {
+        embed.description(dict_lines(found.iter().take(PAGE_SIZE)));
+    }
+    if found.len() > PAGE_SIZE {
+        embed.footer(|footer| footer.text(format!("{} 件中 {} 件を表示", found.len(), PAGE_SIZE)));
+    } else {
+        embed.footer(|footer| footer.text(format!("{} 件", found.len())));
+    }
+    embed
+}
+
+/// 変更履歴の 1 行。登録、変更、削除と取り消しを区別して表示する
+fn history_line(history: &DictHistory) -> String {
+    let change = match (history.before(), history.after()) {
+        (None, Some(new)) => format!("登録 {}", describe(&new)),
+        (Some(old), Some(new)) => format!("{} から {}", describe(&old), describe(&new)),
+        (Some(old), None) => format!("削除 {}", describe(&old)),
+        (None, None) => "変更なし".to_string(),
+    };
+    let mut line = format!("<t:{}:f> <@{}> ", history.created_at, history.author_id);
+    if history.undo_of.is_some() {
+        line.push_str("取り消し: ");
+    }
+    line.push_str(&change);
+    if history.origin_guild_id.is_none() {
+        line.push_str(" (DM)");
+    }
+    if history.undone {
+        line = format!("~~{}~~ (取り消し済み)", line);
+    }
+    line
+}
+
+/// 単語の変更履歴を新しい順に表示する
+pub fn history(scope: DictScope, word: &str, history: &[DictHistory]) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.title(format!(
+        "{} の「{}」の履歴",
+        scope_name(scope),
+        ellipsize(word)
+    ));
+    if history.is_empty() {
+        embed.description("変更の履歴はないよ");
+    } else {
+        embed.description(
+            history
+                .iter()
+                .map(history_line)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+    embed
+}
+
+/// 取り込み結果の text に読みが変わる単語を 1 行ずつ足す
+///
+/// max_lines 行を超える分と、メッセージの文字数の上限に収まらない分は「ほか N 件」にまとめる
+pub fn import_changes(mut text: String, changed: &[(Dict, Dict)], max_lines: usize) -> String {
+    // 「ほか N 件」を足す分の文字数は空けておく
+    let reserved = format!("\nほか {} 件", changed.len()).chars().count();
+    let mut len = text.chars().count();
+    let mut shown = 0;
+    for (old, new) in changed.iter().take(max_lines) {
+        let line = format!("\n{} から {}", describe(old), describe(new));
+        let line_len = line.chars().count();
+        if len + line_len + reserved > MAX_MESSAGE_LEN {
+            break;
+        }
+        text.push_str(&line);
+        len += line_len;
+        shown += 1;
+    }
+    if shown < changed.len() {
+        text.push_str(&format!("\nほか {} 件", changed.len() - shown));
+    }
+    text
+}
+
+#[test]
+fn parse_action_test() {
+    for scope in [DictScope::Global, DictScope::Guild(12), DictScope::User(34)] {
+        let custom_id = format!("{}:{}:5", DICT_LIST_MENU, scope_key(scope));
+        assert_eq!(parse_action(&custom_id), Some((scope, 5)));
+    }
+    assert_eq!(parse_action("dict_list:guild:x:0"), None);
+    assert_eq!(parse_action("dict_list:global"), None);
+    assert_eq!(parse_action("dict_list:other:0"), None);
+    assert_eq!(parse_action("voice_type:page:0"), None);
+}
+
+#[test]
+fn search_test() {
+    let dicts = vec![
+        Dict::literal("nap", "なっぷ"),
+        Dict::literal("bot", "ぼっと"),
+        Dict::literal("nap-chan", "なっぷちゃん"),
+    ];
+    let words = |found: Vec<Dict>| found.into_iter().map(|d| d.word).collect::<Vec<_>>();
+    assert_eq!(words(search(dicts.clone(), "nap")), vec!["nap", "nap-chan"]);
+    assert_eq!(words(search(dicts.clone(), "ぼ")), vec!["bot"]);
+    assert!(search(dicts, "xyz").is_empty());
+    assert_eq!(page_count(41), 3);
+}
+
+#[test]
+fn history_line_test() {
+    let history = DictHistory {
+        id: 2,
+        word: "nap".to_string(),
+        author_id: 100,
+        origin_guild_id: Some(1),
+        created_at: 1_700_000_000,
+        old_read_word: Some("なっぷ".to_string()),
+        old_match_mode: Some(MatchMode::Literal),
+        new_read_word: Some("なっぷちゃん".to_string()),
+        new_match_mode: Some(MatchMode::WholeWord),
+        undo_of: None,
+        undone: true,
+    };
+    assert_eq!(
+        history_line(&history),
+        "~~<t:1700000000:f> <@100> nap → なっぷ から nap → なっぷちゃん (単語単位)~~ (取り消し済み)"
+    );
+    let removed = DictHistory {
+        new_read_word: None,
+        new_match_mode: None,
+        undo_of: Some(1),
+        undone: false,
+        ..history
+    };
+    assert_eq!(
+        history_line(&removed),
+        "<t:1700000000:f> <@100> 取り消し: 削除 nap → なっぷ"
+    );
+}
+
+#[test]
+fn import_changes_test() {
+    let changed = (0..30)
+        .map(|i| {
+            (
+                Dict::literal(&i.to_string(), "よみ"),
+                Dict::literal(&i.to_string(), "よみかた"),
+            )
+        })
+        .collect::<Vec<_>>();
+    let text = import_changes("結果".to_string(), &changed[..2], 20);
+    assert_eq!(
+        text,
+        "結果\n0 → よみ から 0 → よみかた\n1 → よみ から 1 → よみかた"
+    );
+    let text = import_changes("結果".to_string(), &changed, 20);
+    assert_eq!(text.lines().count(), 22);
+    assert!(text.ends_with("\nほか 10 件"));
+    // 長い単語ばかりでもメッセージの文字数の上限に収める
+    let long = "あ".repeat(100);
+    let changed = (0..20)
+        .map(|_| (Dict::literal(&long, &long), Dict::literal(&long, "い")))
+        .collect::<Vec<_>>();
+    let text = import_changes("結果".to_string(), &changed, 20);
+    assert!(text.chars().count() <= MAX_MESSAGE_LEN);
+    assert!(text.ends_with(" 件"));
+    assert!(!text.ends_with("ほか 0 件"));
+}
@@ -0,0 +1,475 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use serenity::async_trait;
+use sqlx::SqlitePool;
+use tokio::sync::Mutex;
+use tracing::info;
+
+#[cfg(test)]
+use super::db::{MatchMode, TEST_EDITOR};
+use super::{
+    db::{DictContext, DictDB, DictEditor, DictHistory, DictScope},
+    dict_matcher::{self, DictMatcher},
+};
+use crate::Dict;
+
+/// DictContext ごとの DictMatcher をこれより多くは持たない
+const MAX_CACHED_MATCHERS: usize = 1024;
+
+/// DictContext ごとの DictMatcher。max を超えたら最後に使ったのが一番古いものを捨てる
+#[derive(Debug)]
+struct Matchers {
+    max: usize,
+    entries: HashMap<DictContext, (Arc<DictMatcher>, u64)>,
+    tick: u64,
+}
+
+impl Matchers {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            entries: HashMap::new(),
+            tick: 0,
+        }
+    }
+    fn get(&mut self, context: &DictContext) -> Option<Arc<DictMatcher>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(context).map(|(matcher, used)| {
+            *used = tick;
+            matcher.clone()
+        })
+    }
+    fn insert(&mut self, context: DictContext, matcher: Arc<DictMatcher>) {
+        if self.entries.len() >= self.max && !self.entries.contains_key(&context) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(context, _)| *context);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(context, (matcher, self.tick));
+    }
+}
+
+/// メモリ上に読み込んだ辞書
+///
+/// 単語は読み込んだ後は変えない。辞書が変わったら新しく読み込んだものと取り替える
+#[derive(Debug)]
+struct DictCache {
+    /// 辞書ごとの 単語 → 辞書の単語
+    entries: HashMap<DictScope, HashMap<String, Dict>>,
+    matchers: std::sync::Mutex<Matchers>,
+}
+
+impl DictCache {
+    fn new(rows: Vec<(DictScope, Dict)>) -> Self {
+        let mut entries: HashMap<DictScope, HashMap<String, Dict>> = HashMap::new();
+        for (scope, dict) in rows {
+            entries
+                .entry(scope)
+                .or_default()
+                .insert(dict.word.clone(), dict);
+        }
+        DictCache {
+            entries,
+            matchers: std::sync::Mutex::new(Matchers::new(MAX_CACHED_MATCHERS)),
+        }
+    }
+    fn scope(&self, scope: DictScope) -> impl Iterator<Item = Dict> + '_ {
+        self.entries
+            .get(&scope)
+            .into_iter()
+            .flat_map(|entries| entries.values())
+            .cloned()
+    }
+    /// 辞書を持っていないギルドやユーザーを取り除いて、同じ結果になる DictContext をまとめる
+    fn normalize(&self, context: DictContext) -> DictContext {
+        let has_entries = |scope| self.entries.get(&scope).is_some_and(|e| !e.is_empty());
+        DictContext {
+            guild_id: context
+                .guild_id
+                .filter(|guild_id| has_entries(DictScope::Guild(*guild_id))),
+            user_id: context
+                .user_id
+                .filter(|user_id| has_entries(DictScope::User(*user_id))),
+        }
+    }
+    fn dict_for(&self, context: DictContext) -> Vec<Dict> {
+        let mut scopes = Vec::new();
+        if let Some(user_id) = context.user_id {
+            scopes.push(DictScope::User(user_id));
+        }
+        if let Some(guild_id) = context.guild_id {
+            scopes.push(DictScope::Guild(guild_id));
+        }
+        scopes.push(DictScope::Global);
+        let mut dicts: Vec<Dict> = Vec::new();
+        let mut words = std::collections::HashSet::new();
+        for scope in scopes {
+            for dict in self.scope(scope) {
+                if words.insert(dict.word.clone()) {
+                    dicts.push(dict);
+                }
+            }
+        }
+        dicts
+    }
+    fn matcher(&self, context: DictContext) -> Arc<DictMatcher> {
+        let context = self.normalize(context);
+        let cached = self.matchers.lock().unwrap().get(&context);
+        if let Some(matcher) = cached {
+            return matcher;
+        }
+        // 作るのに時間がかかるので、ロックを持たずに作る
+        let matcher = Arc::new(DictMatcher::new(&self.dict_for(context)));
+        self.matchers
+            .lock()
+            .unwrap()
+            .insert(context, matcher.clone());
+        matcher
+    }
+}
+
+/// 辞書をメモリ上に読み込んでおく DictDB
+///
+/// 読み込みはメモリ上の辞書だけを使い、変更されたときはデータベースに書き込んでから読み込み直す。
+/// 読み込み直している間は前の辞書で読み上げる
+#[derive(Debug)]
+pub struct Dictionary {
+    database: SqlitePool,
+    /// 使うときはすぐに手放すので std の Mutex を使う
+    cache: std::sync::Mutex<Option<Arc<DictCache>>>,
+    /// 読み込み直すのを 1 つずつにする
+    reloading: Mutex<()>,
+}
+
+impl Dictionary {
+    pub fn new(database: SqlitePool) -> Self {
+        Self {
+            database,
+            cache: std::sync::Mutex::new(None),
+            reloading: Mutex::new(()),
+        }
+    }
+    /// 辞書を読み込んだ状態で作る
+    pub async fn load(database: SqlitePool) -> Result<Self> {
+        let dictionary = Self::new(database);
+        dictionary.snapshot().await?;
+        Ok(dictionary)
+    }
+    fn current(&self) -> Option<Arc<DictCache>> {
+        self.cache.lock().unwrap().clone()
+    }
+    /// 今の辞書。まだ読み込んでいなければ読み込む
+    async fn snapshot(&self) -> Result<Arc<DictCache>> {
+        if let Some(cache) = self.current() {
+            return Ok(cache);
+        }
+        let _reloading = self.reloading.lock().await;
+        // 待っている間に読み込まれていればそれを使う
+        if let Some(cache) = self.current() {
+            return Ok(cache);
+        }
+        self.load_cache().await
+    }
+    /// reloading を持っているときだけ呼ぶ
+    async fn load_cache(&self) -> Result<Arc<DictCache>> {
+        let cache = Arc::new(DictCache::new(self.database.get_dict_every_scope().await?));
+        // ほとんどの発言は全体の辞書だけを使うので先に作っておく
+        cache.matcher(DictContext::default());
+        *self.cache.lock().unwrap() = Some(cache.clone());
+        Ok(cache)
+    }
+    /// 辞書が変わったので読み込み直す
+    async fn reload(&self) {
+        let _reloading = self.reloading.lock().await;
+        if let Err(e) = self.load_cache().await {
+            // 読み込めなければ次に使うときにもう一度読み込む
+            info!("failed to reload dictionary: {}", e);
+            *self.cache.lock().unwrap() = None;
+        }
+    }
+    pub async fn matcher(&self, context: DictContext) -> Result<Arc<DictMatcher>> {
+        Ok(self.snapshot().await?.matcher(context))
+    }
+}
+
+#[async_trait]
+impl DictDB for Dictionary {
+    async fn update_dict(&self, scope: DictScope, dict: &Dict, editor: DictEditor) -> Result<u64> {
+        dict_matcher::validate(dict)?;
+        let result = self.database.update_dict(scope, dict, editor).await;
+        self.reload().await;
+        result
+    }
+    async fn get_dict(&self, scope: DictScope, word: &str) -> Result<Dict> {
+        self.snapshot()
+            .await?
+            .entries
+            .get(&scope)
+            .and_then(|entries| entries.get(word))
+            .cloned()
+            .ok_or_else(|| anyhow!("key not found"))
+    }
+    async fn get_dict_all(&self, scope: DictScope) -> Result<Vec<Dict>> {
+        Ok(self.snapshot().await?.scope(scope).collect())
+    }
+    async fn get_dict_every_scope(&self) -> Result<Vec<(DictScope, Dict)>> {
+        let cache = self.snapshot().await?;
+        Ok(cache
+            .entries
+            .keys()
+            .flat_map(|scope| cache.scope(*scope).map(|dict| (*scope, dict)))
+            .collect())
+    }
+    async fn remove(&self, scope: DictScope, word: &str, editor: DictEditor) -> Result<()> {
+        let result = self.database.remove(scope, word, editor).await;
+        self.reload().await;
+        result
+    }
+    async fn import_dict(
+        &self,
+        scope: DictScope,
+        dicts: &[Dict],
+        removed: &[String],
+        editor: DictEditor,
+    ) -> Result<()> {
+        for dict in dicts {
+            dict_matcher::validate(dict)?;
+        }
+        let result = self
+            .database
+            .import_dict(scope, dicts, removed, editor)
+            .await;
+        self.reload().await;
+        result
+    }
+    async fn get_dict_history(
+        &self,
+        scope: DictScope,
+        word: &str,
+        limit: i64,
+    ) -> Result<Vec<DictHistory>> {
+        self.database.get_dict_history(scope, word, limit).await
+    }
+    async fn undo_dict(
+        &self,
+        scope: DictScope,
+        word: &str,
+        editor: DictEditor,
+    ) -> Result<DictHistory> {
+        let result = self.database.undo_dict(scope, word, editor).await;
+        self.reload().await;
+        result
+    }
+}
+
+#[tokio::test]
+async fn priority_test() {
+    let dictionary = Dictionary::new(super::db::memory_database().await);
+    for (scope, word, read_word) in [
+        (DictScope::Global, "a", "global"),
+        (DictScope::Global, "b", "global"),
+        (DictScope::Guild(1), "a", "guild"),
+        (DictScope::Guild(2), "b", "other guild"),
+        (DictScope::User(3), "a", "user"),
+    ] {
+        dictionary
+            .update_dict(scope, &Dict::literal(word, read_word), TEST_EDITOR)
+            .await
+            .unwrap();
+    }
+    let resolve = |guild_id, user_id| {
+        let dictionary = &dictionary;
+        async move {
+            dictionary
+                .matcher(DictContext { guild_id, user_id })
+                .await
+                .unwrap()
+                .replace("a,b")
+        }
+    };
+    assert_eq!(resolve(None, None).await, "global,global");
+    assert_eq!(resolve(Some(1), None).await, "guild,global");
+    assert_eq!(resolve(Some(1), Some(3)).await, "user,global");
+    assert_eq!(resolve(Some(2), Some(4)).await, "global,other guild");
+}
+
+#[tokio::test]
+async fn invalidate_test() {
+    let dictionary = Dictionary::new(super::db::memory_database().await);
+    let context = DictContext {
+        guild_id: Some(1),
+        user_id: Some(2),
+    };
+    dictionary
+        .update_dict(
+            DictScope::Global,
+            &Dict::literal("nap", "なっぷ"),
+            TEST_EDITOR,
+        )
+        .await
+        .unwrap();
+    let matcher = dictionary.matcher(context).await.unwrap();
+    assert_eq!(matcher.replace("nap-chan"), "なっぷ-chan");
+    // 辞書を持っていないギルドやユーザーは全体の辞書と同じ DictMatcher を使う
+    assert!(Arc::ptr_eq(
+        &matcher,
+        &dictionary.matcher(DictContext::default()).await.unwrap()
+    ));
+
+    dictionary
+        .update_dict(
+            DictScope::Guild(1),
+            &Dict::literal("nap-chan", "なっぷちゃん"),
+            TEST_EDITOR,
+        )
+        .await
+        .unwrap();
+    let matcher = dictionary.matcher(context).await.unwrap();
+    assert_eq!(matcher.replace("nap-chan"), "なっぷちゃん");
+    assert_eq!(
+        dictionary
+            .get_dict(DictScope::Guild(1), "nap-chan")
+            .await
+            .unwrap()
+            .read_word,
+        "なっぷちゃん"
+    );
+    // 正しくない正規表現は登録できない
+    let invalid = Dict::with_mode("(", "かっこ", MatchMode::Regex);
+    assert!(dictionary
+        .update_dict(DictScope::Guild(1), &invalid, TEST_EDITOR)
+        .await
+        .is_err());
+    assert!(dictionary.get_dict(DictScope::Guild(1), "(").await.is_err());
+
+    dictionary
+        .update_dict(
+            DictScope::User(2),
+            &Dict::literal("nap", "ねっぷ"),
+            TEST_EDITOR,
+        )
+        .await
+        .unwrap();
+    let matcher = dictionary.matcher(context).await.unwrap();
+    assert_eq!(matcher.replace("nap と nap-chan"), "ねっぷ と なっぷちゃん");
+
+    dictionary
+        .remove(DictScope::Guild(1), "nap-chan", TEST_EDITOR)
+        .await
+        .unwrap();
+    let matcher = dictionary.matcher(context).await.unwrap();
+    assert_eq!(matcher.replace("nap-chan"), "ねっぷ-chan");
+    assert!(dictionary
+        .get_dict(DictScope::Guild(1), "nap-chan")
+        .await
+        .is_err());
+}
+
+#[test]
+fn matchers_lru_test() {
+    let mut matchers = Matchers::new(2);
+    let context = |guild_id| DictContext {
+        guild_id: Some(guild_id),
+        user_id: None,
+    };
+    let matcher = Arc::new(DictMatcher::new(&[]));
+    matchers.insert(context(1), matcher.clone());
+    matchers.insert(context(2), matcher.clone());
+    // 1 を使ったので、次に捨てられるのは 2
+    assert!(matchers.get(&context(1)).is_some());
+    matchers.insert(context(3), matcher);
+    assert!(matchers.get(&context(2)).is_none());
+    assert!(matchers.get(&context(1)).is_some());
+    assert!(matchers.get(&context(3)).is_some());
+}
+
+#[tokio::test]
+async fn reload_test() {
+    let dictionary = Dictionary::load(super::db::memory_database().await)
+        .await
+        .unwrap();
+    let _reloading = dictionary.reloading.lock().await;
+    // 読み込み直している間も前の辞書で置き換えられる
+    let matcher = tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        dictionary.matcher(DictContext {
+            guild_id: Some(1),
+            user_id: Some(2),
+        }),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(matcher.replace("nap"), "nap");
+}
+
+#[cfg(test)]
+async fn large_dictionary() -> Dictionary {
+    let database = super::db::memory_database().await;
+    let dicts = (0..10000)
+        .map(|i| Dict::literal(&format!("word{}", i), &format!("よみ{}", i)))
+        .collect::<Vec<_>>();
+    database
+        .import_dict(DictScope::Global, &dicts, &[], TEST_EDITOR)
+        .await
+        .unwrap();
+    Dictionary::load(database).await.unwrap()
+}
+
+#[cfg(test)]
+const LARGE_DICTIONARY_TEXT: (&str, &str) = (
+    "word42 が word9999 って言ってた。nap-chan はかわいい",
+    "よみ42 が よみ9999 って言ってた。nap-chan はかわいい",
+);
+
+#[tokio::test]
+async fn large_dictionary_test() {
+    let dictionary = large_dictionary().await;
+    let context = DictContext {
+        guild_id: Some(1),
+        user_id: Some(2),
+    };
+    let first = dictionary.matcher(context).await.unwrap();
+    let (text, expected) = LARGE_DICTIONARY_TEXT;
+    for _ in 0..10 {
+        let matcher = dictionary.matcher(context).await.unwrap();
+        // 辞書が変わらなければ DictMatcher を作り直さない
+        assert!(Arc::ptr_eq(&matcher, &first));
+        assert_eq!(matcher.replace(text), expected);
+    }
+}
+
+/// 1 万語の辞書での読み込みと 1 発言あたりの時間を表示する。
+/// `cargo test --release dictionary_bench -- --ignored --nocapture` で実行する
+#[tokio::test]
+#[ignore]
+async fn dictionary_bench() {
+    let now = std::time::Instant::now();
+    let dictionary = large_dictionary().await;
+    let context = DictContext {
+        guild_id: Some(1),
+        user_id: Some(2),
+    };
+    dictionary.matcher(context).await.unwrap();
+    println!("load: {} ms", now.elapsed().as_millis());
+
+    let (text, expected) = LARGE_DICTIONARY_TEXT;
+    let count = 1000;
+    let now = std::time::Instant::now();
+    for _ in 0..count {
+        let matcher = dictionary.matcher(context).await.unwrap();
+        assert_eq!(matcher.replace(text), expected);
+    }
+    println!(
+        "per message: {} us",
+        now.elapsed().as_micros() / count as u128
+    );
+}
@@ -0,0 +1,304 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::{
+    db::{UserConfig, UserConfigDB, VoiceType},
+    voice::{VoiceGenerator, VoiceVoxAPI, WebVoiceVoxAPI},
+    voice_cache::{CachedGenerator, VoiceCache},
+};
+
+const WEB_VOICEVOX_API_URL: &str = "https://api.su-shiki.com/v2/voicevox";
+
+pub type Generator = Arc<dyn VoiceGenerator + Send + Sync>;
+
+/// generators.json の各要素
+///
+/// URL だけ書いた場合、フェイルオーバー先として使われるときのボイスタイプは 0 になる
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum GeneratorConfig {
+    Url(String),
+    Detailed {
+        url: String,
+        #[serde(default)]
+        default_voice_type: i64,
+    },
+}
+
+pub struct GeneratorEntry {
+    generator: Generator,
+    default_voice_type: i64,
+    healthy: AtomicBool,
+}
+
+impl GeneratorEntry {
+    pub fn new(generator: Generator, default_voice_type: i64) -> Self {
+        Self {
+            generator,
+            default_voice_type,
+            healthy: AtomicBool::new(true),
+        }
+    }
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+}
+
+/// UserConfig::generator_type をインデックスとする音声合成エンジンの一覧
+///
+/// GENERATORS に書かれた URL が書かれた順に並び、WEB_API_KEY があればその後ろに Web 版 VOICEVOX が入る
+pub struct Generators {
+    entries: Vec<GeneratorEntry>,
+    cache: Option<Arc<VoiceCache>>,
+}
+
+impl Generators {
+    pub fn new(entries: Vec<GeneratorEntry>) -> Self {
+        Self {
+            entries,
+            cache: None,
+        }
+    }
+    /// すべてのエンジンの前に合成した音声のキャッシュを置く
+    pub fn with_cache(mut self, cache: Arc<VoiceCache>) -> Self {
+        for (generator_type, entry) in self.entries.iter_mut().enumerate() {
+            entry.generator = Arc::new(CachedGenerator::new(
+                entry.generator.clone(),
+                generator_type as i64,
+                cache.clone(),
+            ));
+        }
+        self.cache = Some(cache);
+        self
+    }
+    pub fn cache(&self) -> Option<&VoiceCache> {
+        self.cache.as_deref()
+    }
+    pub fn load() -> Result<Self> {
+        dotenv::dotenv().ok();
+        let mut entries = Vec::new();
+        if let Ok(path) = dotenv::var("GENERATORS") {
+            let file = std::fs::File::open(path)?;
+            let configs: Vec<GeneratorConfig> = serde_json::from_reader(file)?;
+            info!("generators = {:?}", &configs);
+            for config in configs {
+                let (url, default_voice_type) = match config {
+                    GeneratorConfig::Url(url) => (url, 0),
+                    GeneratorConfig::Detailed {
+                        url,
+                        default_voice_type,
+                    } => (url, default_voice_type),
+                };
+                entries.push(GeneratorEntry::new(
+                    Arc::new(VoiceVoxAPI::new(url)),
+                    default_voice_type,
+                ));
+            }
+        }
+        if let Ok(api_key) = dotenv::var("WEB_API_KEY") {
+            entries.push(GeneratorEntry::new(
+                Arc::new(WebVoiceVoxAPI::new(
+                    WEB_VOICEVOX_API_URL.to_string(),
+                    api_key,
+                )),
+                0,
+            ));
+        }
+        if entries.is_empty() {
+            return Err(anyhow!("GENERATORS か WEB_API_KEY を設定してください"));
+        }
+        let cache = VoiceCache::from_env();
+        if cache.max_bytes() == 0 {
+            Ok(Self::new(entries))
+        } else {
+            Ok(Self::new(entries).with_cache(Arc::new(cache)))
+        }
+    }
+    fn entry(&self, generator_type: i64) -> Option<&GeneratorEntry> {
+        usize::try_from(generator_type)
+            .ok()
+            .and_then(|idx| self.entries.get(idx))
+    }
+    pub fn is_healthy(&self, generator_type: i64) -> bool {
+        self.entry(generator_type)
+            .is_some_and(GeneratorEntry::is_healthy)
+    }
+    /// ユーザーの設定しているエンジンが落ちていたら、生きているエンジンのデフォルトのボイスに切り替える
+    pub fn resolve(&self, generator_type: i64, voice_type: i64) -> Result<(Generator, i64)> {
+        if let Some(entry) = self.entry(generator_type).filter(|e| e.is_healthy()) {
+            return Ok((entry.generator.clone(), voice_type));
+        }
+        let (fallback_type, entry) = self
+            .entries
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.is_healthy())
+            .ok_or_else(|| anyhow!("no healthy generator"))?;
+        info!(
+            "generator {} is unavailable, fall back to generator {} voice {}",
+            generator_type, fallback_type, entry.default_voice_type
+        );
+        Ok((entry.generator.clone(), entry.default_voice_type))
+    }
+    pub async fn check_health(&self) {
+        for (generator_type, entry) in self.entries.iter().enumerate() {
+            let result = entry.generator.health_check().await;
+            let healthy = result.is_ok();
+            if entry.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                match result {
+                    Ok(()) => info!("generator {} is healthy", generator_type),
+                    Err(e) => info!("generator {} is unhealthy: {}", generator_type, e),
+                }
+            }
+        }
+    }
+    pub fn spawn_health_check(self: &Arc<Self>, period: Duration) {
+        let generators = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                generators.check_health().await;
+            }
+        });
+    }
+    /// 全エンジンのボイスタイプを取得する。取得できなかったエンジンについては previous のものをそのまま使う
+    pub async fn get_voice_types(&self, previous: &[VoiceType]) -> Vec<VoiceType> {
+        let mut voice_types = Vec::new();
+        for (generator_type, entry) in self.entries.iter().enumerate() {
+            let generator_type = generator_type as i64;
+            match entry.generator.get_voice_types(generator_type).await {
+                Ok(mut v) => voice_types.append(&mut v),
+                Err(e) => {
+                    info!("generator {}: {}", generator_type, e);
+                    voice_types.extend(
+                        previous
+                            .iter()
+                            .filter(|voice_type| voice_type.generator_type == generator_type)
+                            .cloned(),
+                    );
+                }
+            }
+        }
+        voice_types
+    }
+}
+
+/// voice_types を取得し直し、存在しないボイスタイプを設定しているユーザーの設定を返す
+pub async fn reload_voice_types(
+    generators: &Generators,
+    voice_types: &Mutex<Vec<VoiceType>>,
+    database: &SqlitePool,
+) -> Result<Vec<UserConfig>> {
+    let previous = voice_types.lock().await.clone();
+    let reloaded = generators.get_voice_types(&previous).await;
+    let stale_user_configs = database
+        .get_user_config_all()
+        .await?
+        .into_iter()
+        .filter(|user_config| {
+            !reloaded.iter().any(|voice_type| {
+                voice_type.generator_type == user_config.generator_type
+                    && voice_type.style_id as i64 == user_config.voice_type
+            })
+        })
+        .collect();
+    info!(
+        "reloaded voice types: {} -> {}",
+        previous.len(),
+        reloaded.len()
+    );
+    *voice_types.lock().await = reloaded;
+    Ok(stale_user_configs)
+}
+
+pub fn spawn_voice_reload(
+    generators: Arc<Generators>,
+    voice_types: Arc<Mutex<Vec<VoiceType>>>,
+    database: SqlitePool,
+    period: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // 起動時には取得済みなので最初の tick は飛ばす
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match reload_voice_types(&generators, &voice_types, &database).await {
+                Ok(stale) if !stale.is_empty() => {
+                    info!("users with unavailable voice type: {:?}", stale)
+                }
+                Ok(_) => (),
+                Err(e) => info!("{:?}", e),
+            }
+        }
+    });
+}
+
+// /version に 200 を返すだけのサーバーを立てて、その URL を返す
+#[cfg(test)]
+fn mock_engine() -> String {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n\"0.1.0\"");
+        }
+    });
+    url
+}
+
+// 接続を受け付けないアドレスの URL を返す
+#[cfg(test)]
+fn dead_engine() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[tokio::test]
+async fn health_check_test() {
+    let generators = Generators::new(vec![
+        GeneratorEntry::new(Arc::new(VoiceVoxAPI::new(dead_engine())), 0),
+        GeneratorEntry::new(Arc::new(VoiceVoxAPI::new(mock_engine())), 0),
+    ]);
+    assert!(generators.is_healthy(0));
+    generators.check_health().await;
+    assert!(!generators.is_healthy(0));
+    assert!(generators.is_healthy(1));
+    assert!(!generators.is_healthy(2));
+}
+
+#[tokio::test]
+async fn failover_test() {
+    let generators = Generators::new(vec![
+        GeneratorEntry::new(Arc::new(VoiceVoxAPI::new(dead_engine())), 1),
+        GeneratorEntry::new(Arc::new(VoiceVoxAPI::new(mock_engine())), 3),
+    ]);
+    generators.check_health().await;
+    // 生きているエンジンはそのまま使う
+    assert_eq!(generators.resolve(1, 8).unwrap().1, 8);
+    // 落ちているエンジンや存在しないエンジンは生きているエンジンのデフォルトに切り替わる
+    assert_eq!(generators.resolve(0, 8).unwrap().1, 3);
+    assert_eq!(generators.resolve(5, 8).unwrap().1, 3);
+
+    let generators = Generators::new(vec![GeneratorEntry::new(
+        Arc::new(VoiceVoxAPI::new(dead_engine())),
+        0,
+    )]);
+    generators.check_health().await;
+    assert!(generators.resolve(0, 1).is_err());
+}
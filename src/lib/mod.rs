@@ -1,3 +1,15 @@
 pub mod db;
+pub mod dict_file;
+pub mod dict_matcher;
+pub mod dict_view;
+pub mod dictionary;
+pub mod generator;
+pub mod permission;
+pub mod pipeline;
+pub mod queue;
+pub mod session;
 pub mod text;
 pub mod voice;
+pub mod voice_cache;
+pub mod voice_picker;
+pub mod wav;
@@ -0,0 +1,253 @@
+use poise::serenity_prelude::{Permissions, RoleId};
+
+use super::db::{CommandGroup, PermissionRule};
+
+/// /config permissions で選べる Discord の権限
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum RequiredPermission {
+    #[name = "everyone"]
+    #[name_localized("ja", "全員")]
+    Everyone,
+    #[name = "administrator"]
+    #[name_localized("ja", "管理者")]
+    Administrator,
+    #[name = "manage_guild"]
+    #[name_localized("ja", "サーバー管理")]
+    ManageGuild,
+    #[name = "manage_channels"]
+    #[name_localized("ja", "チャンネルの管理")]
+    ManageChannels,
+    #[name = "manage_messages"]
+    #[name_localized("ja", "メッセージの管理")]
+    ManageMessages,
+    #[name = "manage_roles"]
+    #[name_localized("ja", "ロールの管理")]
+    ManageRoles,
+    #[name = "mute_members"]
+    #[name_localized("ja", "メンバーをミュート")]
+    MuteMembers,
+    #[name = "move_members"]
+    #[name_localized("ja", "メンバーを移動")]
+    MoveMembers,
+}
+
+impl RequiredPermission {
+    const ALL: [RequiredPermission; 8] = [
+        RequiredPermission::Everyone,
+        RequiredPermission::Administrator,
+        RequiredPermission::ManageGuild,
+        RequiredPermission::ManageChannels,
+        RequiredPermission::ManageMessages,
+        RequiredPermission::ManageRoles,
+        RequiredPermission::MuteMembers,
+        RequiredPermission::MoveMembers,
+    ];
+    fn from_bits(bits: i64) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|permission| permission.permissions().bits() as i64 == bits)
+    }
+    /// 返事に使う権限の名前。英語は Discord の画面での名前に合わせる
+    pub fn label(self, locale: Option<&str>) -> &'static str {
+        if is_japanese(locale) {
+            return self.localized_name("ja").unwrap_or_else(|| self.name());
+        }
+        match self {
+            RequiredPermission::Everyone => "Everyone",
+            RequiredPermission::Administrator => "Administrator",
+            RequiredPermission::ManageGuild => "Manage Server",
+            RequiredPermission::ManageChannels => "Manage Channels",
+            RequiredPermission::ManageMessages => "Manage Messages",
+            RequiredPermission::ManageRoles => "Manage Roles",
+            RequiredPermission::MuteMembers => "Mute Members",
+            RequiredPermission::MoveMembers => "Move Members",
+        }
+    }
+    pub fn permissions(self) -> Permissions {
+        match self {
+            RequiredPermission::Everyone => Permissions::empty(),
+            RequiredPermission::Administrator => Permissions::ADMINISTRATOR,
+            RequiredPermission::ManageGuild => Permissions::MANAGE_GUILD,
+            RequiredPermission::ManageChannels => Permissions::MANAGE_CHANNELS,
+            RequiredPermission::ManageMessages => Permissions::MANAGE_MESSAGES,
+            RequiredPermission::ManageRoles => Permissions::MANAGE_ROLES,
+            RequiredPermission::MuteMembers => Permissions::MUTE_MEMBERS,
+            RequiredPermission::MoveMembers => Permissions::MOVE_MEMBERS,
+        }
+    }
+}
+
+/// 設定がないときの既定値。管理者用コマンドは管理者だけ、読み上げない人の設定はメッセージの管理の権限を持つ人だけ、
+/// それ以外は誰でも使える
+pub fn default_rule(guild_id: i64, command: CommandGroup) -> PermissionRule {
+    let permissions = match command {
+        CommandGroup::Admin => Permissions::ADMINISTRATOR,
+        CommandGroup::Ignore => Permissions::MANAGE_MESSAGES,
+        _ => Permissions::empty(),
+    };
+    PermissionRule {
+        guild_id,
+        command,
+        permissions: Some(permissions.bits() as i64),
+        role_id: None,
+    }
+}
+
+/// permissions と roles を持つメンバーが rule で制限されたコマンドを使えるか
+///
+/// 管理者権限を持っていれば設定に関係なく使える
+pub fn allows(rule: &PermissionRule, permissions: Permissions, roles: &[RoleId]) -> bool {
+    permissions.administrator()
+        || rule
+            .permissions
+            .is_some_and(|bits| permissions.contains(Permissions::from_bits_truncate(bits as u64)))
+        || rule
+            .role_id
+            .is_some_and(|role_id| roles.contains(&RoleId(role_id as u64)))
+}
+
+pub fn is_japanese(locale: Option<&str>) -> bool {
+    locale.is_none_or(|locale| locale.starts_with("ja"))
+}
+
+/// rule で必要なものを「A か B」の形で表示する
+pub fn describe(rule: &PermissionRule, locale: Option<&str>) -> String {
+    let japanese = is_japanese(locale);
+    let mut requirements = Vec::new();
+    if let Some(bits) = rule.permissions {
+        // /config permissions で選べない権限はデータベースを直接書き換えたときだけなので、Discord の名前で出す
+        let name = match RequiredPermission::from_bits(bits) {
+            Some(RequiredPermission::Everyone) => None,
+            Some(permission) => Some(permission.label(locale).to_string()),
+            None => Some(
+                Permissions::from_bits_truncate(bits as u64)
+                    .get_permission_names()
+                    .join(", "),
+            ),
+        };
+        requirements.push(match (name, japanese) {
+            (None, true) => "なし (全員)".to_string(),
+            (None, false) => "nothing (everyone)".to_string(),
+            (Some(name), true) => format!("{} の権限", name),
+            (Some(name), false) => format!("the {} permission", name),
+        });
+    }
+    if let Some(role_id) = rule.role_id {
+        requirements.push(if japanese {
+            format!("<@&{}> のロール", role_id)
+        } else {
+            format!("the <@&{}> role", role_id)
+        });
+    }
+    requirements.join(if japanese { " か " } else { " or " })
+}
+
+/// コマンドを使えなかった人への返事。locale が日本語以外なら英語で返す
+///
+/// rules には満たしていないものをすべて渡す。空なら何が必要かは書かない
+pub fn refusal(rules: &[PermissionRule], locale: Option<&str>) -> String {
+    let japanese = is_japanese(locale);
+    let requirements = rules
+        .iter()
+        .map(|rule| describe(rule, locale))
+        .collect::<Vec<_>>()
+        .join(if japanese { " と " } else { " and " });
+    match (rules.is_empty(), japanese) {
+        (false, true) => format!("このコマンドを使うには {} が必要だよ", requirements),
+        (false, false) => format!("You need {} to use this command.", requirements),
+        (true, true) => "このコマンドを使う権限がないよ".to_string(),
+        (true, false) => "You don't have permission to use this command.".to_string(),
+    }
+}
+
+/// bot のオーナーだけが使えるコマンドを使おうとした人への返事
+pub fn owner_refusal(locale: Option<&str>) -> &'static str {
+    if is_japanese(locale) {
+        "このコマンドは bot のオーナーだけが使えるよ"
+    } else {
+        "Only the bot owners can use this command."
+    }
+}
+
+#[cfg(test)]
+fn rule(permissions: Option<Permissions>, role_id: Option<i64>) -> PermissionRule {
+    PermissionRule {
+        guild_id: 1,
+        command: CommandGroup::Add,
+        permissions: permissions.map(|permissions| permissions.bits() as i64),
+        role_id,
+    }
+}
+
+#[test]
+fn allows_test() {
+    let member = Permissions::SEND_MESSAGES | Permissions::MUTE_MEMBERS;
+    let roles = [RoleId(10)];
+    assert!(allows(&default_rule(1, CommandGroup::Add), member, &[]));
+    assert!(!allows(
+        &default_rule(1, CommandGroup::Admin),
+        member,
+        &roles
+    ));
+    assert!(allows(
+        &default_rule(1, CommandGroup::Admin),
+        Permissions::ADMINISTRATOR,
+        &[]
+    ));
+    assert!(allows(
+        &rule(Some(Permissions::MUTE_MEMBERS), None),
+        member,
+        &[]
+    ));
+    assert!(!allows(
+        &rule(Some(Permissions::MANAGE_GUILD), None),
+        member,
+        &[]
+    ));
+    // 権限とロールのどちらかがあれば使える
+    assert!(allows(
+        &rule(Some(Permissions::MANAGE_GUILD), Some(10)),
+        member,
+        &roles
+    ));
+    assert!(!allows(&rule(None, Some(11)), member, &roles));
+}
+
+#[test]
+fn refusal_test() {
+    let rule = rule(Some(Permissions::MANAGE_GUILD), Some(10));
+    let rules = [rule.clone()];
+    assert_eq!(
+        refusal(&rules, Some("ja")),
+        "このコマンドを使うには サーバー管理 の権限 か <@&10> のロール が必要だよ"
+    );
+    assert_eq!(
+        refusal(&rules, Some("en-US")),
+        "You need the Manage Server permission or the <@&10> role to use this command."
+    );
+    assert_eq!(refusal(&rules, None), refusal(&rules, Some("ja")));
+    assert_eq!(
+        refusal(&[], Some("en-GB")),
+        "You don't have permission to use this command."
+    );
+    // 満たしていないものはすべて書く
+    let other = PermissionRule {
+        command: CommandGroup::Rem,
+        ..default_rule(1, CommandGroup::Ignore)
+    };
+    assert_eq!(
+        refusal(&[rule.clone(), other], Some("ja")),
+        "このコマンドを使うには サーバー管理 の権限 か <@&10> のロール と メッセージの管理 の権限 が必要だよ"
+    );
+    assert_eq!(
+        describe(&default_rule(1, CommandGroup::Add), Some("en-US")),
+        "nothing (everyone)"
+    );
+    // 選べない権限は Discord の名前で出す
+    let unknown = PermissionRule {
+        permissions: Some(Permissions::BAN_MEMBERS.bits() as i64),
+        role_id: None,
+        ..rule
+    };
+    assert_eq!(describe(&unknown, Some("ja")), "Ban Members の権限");
+}
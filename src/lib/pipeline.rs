@@ -0,0 +1,178 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use futures::{
+    channel::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
+    future::BoxFuture,
+    FutureExt, StreamExt,
+};
+use serenity::model::id::GuildId;
+
+/// 再生待ちに入れる後半の仕事
+pub type Enqueue = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+/// 前半で音声を合成して、後半を返す仕事
+pub type Job = BoxFuture<'static, Enqueue>;
+
+/// reserve で取っておいた順番。fill で仕事を入れる。入れずに drop するとその順番は飛ばす
+pub struct Slot {
+    sender: oneshot::Sender<Job>,
+}
+
+impl Slot {
+    pub fn fill(self, job: Job) {
+        self.sender.send(job).ok();
+    }
+}
+
+/// ギルドごとに、発言の順番を保ったまま音声を合成する
+///
+/// 前半は parallelism 個まで同時に進めるが、後半は入れた順にしか実行しない
+pub struct SynthesisPipeline {
+    parallelism: usize,
+    workers: Mutex<HashMap<GuildId, UnboundedSender<Job>>>,
+}
+
+impl SynthesisPipeline {
+    pub fn new(parallelism: usize) -> Self {
+        Self {
+            parallelism: parallelism.max(1),
+            workers: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("SYNTHESIS_PARALLELISM")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(2),
+        )
+    }
+    pub fn push(&self, guild_id: GuildId, job: Job) {
+        let mut workers = self.workers.lock().unwrap();
+        let job = match workers.get(&guild_id) {
+            Some(sender) => match sender.unbounded_send(job) {
+                Ok(()) => return,
+                Err(e) => e.into_inner(),
+            },
+            None => job,
+        };
+        let (sender, receiver) = mpsc::unbounded::<Job>();
+        tokio::spawn(
+            receiver
+                .buffered(self.parallelism)
+                .for_each(|enqueue| enqueue()),
+        );
+        sender.unbounded_send(job).ok();
+        workers.insert(guild_id, sender);
+    }
+    /// 仕事を用意する前に順番だけ取っておく
+    ///
+    /// 発言が届いてすぐに呼べば、仕事を用意するまでの await の長さによらず届いた順に再生待ちに入る
+    pub fn reserve(&self, guild_id: GuildId) -> Slot {
+        let (sender, receiver) = oneshot::channel::<Job>();
+        self.push(
+            guild_id,
+            async move {
+                match receiver.await {
+                    Ok(job) => job.await,
+                    Err(_) => Box::new(|| async {}.boxed()) as Enqueue,
+                }
+            }
+            .boxed(),
+        );
+        Slot { sender }
+    }
+    /// ボイスチャンネルから抜けたギルドのパイプラインを片付ける。合成中のものは最後まで実行する
+    pub fn remove(&self, guild_id: GuildId) {
+        self.workers.lock().unwrap().remove(&guild_id);
+    }
+}
+
+#[tokio::test]
+async fn pipeline_order_test() {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    let pipeline = SynthesisPipeline::new(2);
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let running = Arc::new(AtomicUsize::new(0));
+    let max_running = Arc::new(AtomicUsize::new(0));
+    let (done_sender, done_receiver) = tokio::sync::oneshot::channel();
+    let mut done_sender = Some(done_sender);
+    // 後の発言ほど合成が早く終わる
+    for i in 0..5u64 {
+        let order = order.clone();
+        let running = running.clone();
+        let max_running = max_running.clone();
+        let done_sender = if i == 4 { done_sender.take() } else { None };
+        let job = async move {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50 - i * 10)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+            Box::new(move || {
+                async move {
+                    order.lock().unwrap().push(i);
+                    if let Some(done_sender) = done_sender {
+                        done_sender.send(()).ok();
+                    }
+                }
+                .boxed()
+            }) as Enqueue
+        }
+        .boxed();
+        pipeline.push(GuildId(1), job);
+    }
+    done_receiver.await.unwrap();
+    assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(max_running.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn pipeline_reserve_test() {
+    use std::{sync::Arc, time::Duration};
+
+    let pipeline = SynthesisPipeline::new(2);
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let (done_sender, done_receiver) = tokio::sync::oneshot::channel();
+    let job = |i: u64, done_sender: Option<tokio::sync::oneshot::Sender<()>>| {
+        let order = order.clone();
+        async move {
+            Box::new(move || {
+                async move {
+                    order.lock().unwrap().push(i);
+                    if let Some(done_sender) = done_sender {
+                        done_sender.send(()).ok();
+                    }
+                }
+                .boxed()
+            }) as Enqueue
+        }
+        .boxed()
+    };
+    // 先に届いた発言のほうが合成の前の準備に時間がかかる
+    let first = pipeline.reserve(GuildId(1));
+    let skipped = pipeline.reserve(GuildId(1));
+    let second = pipeline.reserve(GuildId(1));
+    let slow = tokio::spawn({
+        let job = job(0, None);
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            first.fill(job);
+        }
+    });
+    second.fill(job(1, Some(done_sender)));
+    // 読み上げない発言の順番は飛ばす
+    drop(skipped);
+    done_receiver.await.unwrap();
+    slow.await.unwrap();
+    assert_eq!(*order.lock().unwrap(), vec![0, 1]);
+}
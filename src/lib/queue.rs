@@ -0,0 +1,217 @@
+use serenity::{model::id::UserId, prelude::TypeMapKey};
+use songbird::tracks::{TrackHandle, TrackQueue};
+
+/// /queue で表示する本文の最大文字数
+const PREVIEW_LEN: usize = 30;
+
+/// /queue で表示する発話の最大数
+const MAX_DISPLAYED: usize = 10;
+
+/// 発話の種類。チャット以外は読み上げ待ちのチャットより先に読む
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UtteranceKind {
+    #[default]
+    Chat,
+    /// 入室時のあいさつ
+    Hello,
+    /// 退室時のあいさつ
+    Bye,
+    /// 試聴など bot からのお知らせ
+    Announcement,
+}
+
+impl UtteranceKind {
+    pub fn is_priority(self) -> bool {
+        self != UtteranceKind::Chat
+    }
+}
+
+/// 読み上げ待ちの発話が誰の何という文章か。track の typemap に入れておく
+#[derive(Debug, Clone)]
+pub struct Utterance {
+    /// あいさつや試聴も話した人の発話として扱う
+    pub author_id: Option<UserId>,
+    pub text: String,
+    pub kind: UtteranceKind,
+}
+
+impl TypeMapKey for Utterance {
+    type Value = Utterance;
+}
+
+/// 再生中のものを先頭にした読み上げ待ちの発話。typemap に何もない track は None
+pub async fn utterances(handles: &[TrackHandle]) -> Vec<Option<Utterance>> {
+    let mut utterances = Vec::with_capacity(handles.len());
+    for handle in handles {
+        utterances.push(handle.typemap().read().await.get::<Utterance>().cloned());
+    }
+    utterances
+}
+
+/// typemap を読めなければチャットとして扱う。書き込むのは enqueue する前だけなので普通は読める
+fn queued_utterance(handle: &TrackHandle) -> Option<Utterance> {
+    handle
+        .typemap()
+        .try_read()
+        .ok()?
+        .get::<Utterance>()
+        .cloned()
+}
+
+fn queued_kind(handle: &TrackHandle) -> UtteranceKind {
+    queued_utterance(handle).map_or(UtteranceKind::Chat, |utterance| utterance.kind)
+}
+
+/// 優先する発話を入れる位置。再生中の先頭と、先に入っている優先する発話の後ろに入れる
+fn priority_index(kinds: &[UtteranceKind]) -> usize {
+    match kinds.split_first() {
+        None => 0,
+        Some((_, rest)) => 1 + rest.iter().take_while(|kind| kind.is_priority()).count(),
+    }
+}
+
+/// 末尾に enqueue した発話が優先するものなら、読み上げ待ちのチャットより前に移す
+pub fn prioritize_last(queue: &TrackQueue) {
+    queue.modify_queue(|tracks| {
+        let last = match tracks.back() {
+            Some(last) if queued_kind(last).is_priority() => tracks.len() - 1,
+            _ => return,
+        };
+        let kinds = tracks
+            .iter()
+            .take(last)
+            .map(|queued| queued_kind(queued))
+            .collect::<Vec<_>>();
+        let index = priority_index(&kinds);
+        if index < last {
+            if let Some(queued) = tracks.remove(last) {
+                tracks.insert(index, queued);
+            }
+        }
+    });
+}
+
+/// 読み上げ待ちが max_queued より多ければ、再生中のもの以外の古いチャットから捨てる
+pub fn drop_overflow(queue: &TrackQueue, max_queued: usize) {
+    while queue.len() > max_queued + 1 {
+        let index = queue.modify_queue(|tracks| {
+            tracks
+                .iter()
+                .skip(1)
+                .position(|queued| !queued_kind(queued).is_priority())
+                .map_or(1, |position| position + 1)
+        });
+        match queue.dequeue(index) {
+            Some(queued) => {
+                queued.handle().stop().ok();
+            }
+            None => break,
+        }
+    }
+}
+
+/// author_id の人の、まだ再生していない入室時のあいさつを捨てる。捨てたら true
+pub fn drop_hello(queue: &TrackQueue, author_id: UserId) -> bool {
+    let dropped = queue.modify_queue(|tracks| {
+        let mut dropped = Vec::new();
+        let mut index = 1;
+        while index < tracks.len() {
+            let is_hello = queued_utterance(&tracks[index]).is_some_and(|utterance| {
+                utterance.kind == UtteranceKind::Hello && utterance.author_id == Some(author_id)
+            });
+            if is_hello {
+                dropped.extend(tracks.remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        dropped
+    });
+    for queued in dropped.iter() {
+        queued.handle().stop().ok();
+    }
+    !dropped.is_empty()
+}
+
+fn preview(text: &str) -> String {
+    if text.chars().count() > PREVIEW_LEN {
+        format!("{}…", text.chars().take(PREVIEW_LEN).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+/// /queue の返事。先頭は再生中として表示する
+pub fn queue_text(utterances: &[Option<Utterance>]) -> String {
+    if utterances.is_empty() {
+        return "読み上げ待ちはないよ".to_string();
+    }
+    let mut lines = utterances
+        .iter()
+        .take(MAX_DISPLAYED)
+        .enumerate()
+        .map(|(i, utterance)| {
+            let head = if i == 0 {
+                "再生中".to_string()
+            } else {
+                format!("{}.", i)
+            };
+            match utterance {
+                Some(Utterance {
+                    author_id: Some(author_id),
+                    text,
+                    ..
+                }) => format!("{} <@{}> {}", head, author_id, preview(text)),
+                Some(Utterance {
+                    author_id: None,
+                    text,
+                    ..
+                }) => format!("{} {}", head, preview(text)),
+                None => format!("{} (不明)", head),
+            }
+        })
+        .collect::<Vec<_>>();
+    if utterances.len() > MAX_DISPLAYED {
+        lines.push(format!("ほか {} 件", utterances.len() - MAX_DISPLAYED));
+    }
+    lines.join("\n")
+}
+
+#[test]
+fn queue_text_test() {
+    assert_eq!(queue_text(&[]), "読み上げ待ちはないよ");
+    let utterances = vec![
+        Some(Utterance {
+            author_id: Some(UserId(1)),
+            text: "nap こんにちは".to_string(),
+            kind: UtteranceKind::Chat,
+        }),
+        Some(Utterance {
+            author_id: None,
+            text: "あ".repeat(40),
+            kind: UtteranceKind::Announcement,
+        }),
+        None,
+    ];
+    assert_eq!(
+        queue_text(&utterances),
+        format!(
+            "再生中 <@1> nap こんにちは\n1. {}…\n2. (不明)",
+            "あ".repeat(30)
+        )
+    );
+    let many = vec![None; 12];
+    assert!(queue_text(&many).ends_with("\nほか 2 件"));
+}
+
+#[test]
+fn priority_index_test() {
+    use UtteranceKind::*;
+    assert_eq!(priority_index(&[]), 0);
+    assert_eq!(priority_index(&[Chat]), 1);
+    // 再生中のものはあいさつでも割り込まない
+    assert_eq!(priority_index(&[Hello, Chat, Chat]), 1);
+    // 先に入っているあいさつの順番は変えない
+    assert_eq!(priority_index(&[Chat, Hello, Bye, Chat, Hello]), 3);
+    assert_eq!(priority_index(&[Chat, Announcement]), 2);
+}
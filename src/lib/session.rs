@@ -0,0 +1,24 @@
+use std::{collections::HashSet, time::Instant};
+
+use serenity::model::id::ChannelId;
+
+/// `/join` してから `/leave` するまでの、ギルドごとの読み上げ状態
+#[derive(Debug)]
+pub struct Session {
+    pub voice_channel_id: ChannelId,
+    pub read_channel_ids: HashSet<ChannelId>,
+    pub started_at: Instant,
+}
+
+impl Session {
+    pub fn new(voice_channel_id: ChannelId, read_channel_id: ChannelId) -> Self {
+        Self {
+            voice_channel_id,
+            read_channel_ids: HashSet::from([read_channel_id]),
+            started_at: Instant::now(),
+        }
+    }
+    pub fn is_read_channel(&self, channel_id: ChannelId) -> bool {
+        self.read_channel_ids.contains(&channel_id)
+    }
+}
@@ -2,7 +2,10 @@ use regex;
 use serenity::async_trait;
 use tracing::info;
 
-use super::db::DictDB;
+use super::{
+    db::{DictContext, ReadLimit},
+    dictionary::Dictionary,
+};
 use alkana_rs::ALKANA;
 use wana_kana::{is_katakana::is_katakana, to_katakana::to_katakana};
 
@@ -10,9 +13,9 @@ use wana_kana::{is_katakana::is_katakana, to_katakana::to_katakana};
 pub trait TextMessage {
     fn replace_url(&self) -> Self;
     fn remove_spoiler(&self) -> Self;
-    async fn replace_by_dict<T: DictDB + Sync>(&self, database: &T) -> Self;
+    async fn replace_by_dict(&self, dictionary: &Dictionary, context: DictContext) -> Self;
     fn remove_custom_emoji(&self) -> Self;
-    async fn make_read_text<T: DictDB + Sync>(&self, database: &T) -> Self;
+    async fn make_read_text(&self, dictionary: &Dictionary, context: DictContext) -> Self;
     fn hiraganize(&self) -> Self;
     fn remove_code_block(&self) -> Self;
 }
@@ -26,14 +29,8 @@ impl TextMessage for String {
         let re = regex::Regex::new(r"\|\|[\s\S]*\|\|").unwrap();
         re.replace_all(self, "").to_string()
     }
-    async fn replace_by_dict<T: DictDB + Sync>(&self, database: &T) -> Self {
-        let mut text = self.clone();
-        for w in database.get_dict_all().await.unwrap() {
-            let before = &w.word;
-            let after = &w.read_word;
-            text = text.replace(before, after);
-        }
-        text
+    async fn replace_by_dict(&self, dictionary: &Dictionary, context: DictContext) -> Self {
+        dictionary.matcher(context).await.unwrap().replace(self)
     }
     fn hiraganize(&self) -> Self {
         let re_statement = regex::Regex::new(r"[a-zA-Z]+(\s+[a-zA-Z]+)*").unwrap();
@@ -85,12 +82,12 @@ impl TextMessage for String {
         }
         text
     }
-    async fn make_read_text<T: DictDB + Sync>(&self, database: &T) -> Self {
+    async fn make_read_text(&self, dictionary: &Dictionary, context: DictContext) -> Self {
         self.replace_url()
             .remove_spoiler()
             .remove_code_block()
             .remove_custom_emoji()
-            .replace_by_dict(database)
+            .replace_by_dict(dictionary, context)
             .await
             .hiraganize()
     }
@@ -100,6 +97,44 @@ impl TextMessage for String {
     }
 }
 
+/// 切り詰めたときに最後に読む言葉
+const OMISSION: &str = "以下省略";
+
+fn is_boundary(c: char) -> bool {
+    matches!(c, '。' | '．' | '！' | '？' | '!' | '?' | '.' | '\n')
+}
+
+/// 長すぎる文章を max_lines 行、max_chars 文字までに切り詰めて「以下省略」をつける
+///
+/// なるべく文か行の切れ目で切る。切れ目が上限の半分より前にしかなければ上限の位置で切る
+pub fn truncate(text: &str, limit: ReadLimit) -> String {
+    let mut text = text.trim();
+    let mut truncated = false;
+    if let Some(max_lines) = limit.max_lines {
+        if let Some((index, _)) = text.match_indices('\n').nth(max_lines.saturating_sub(1)) {
+            text = &text[..index];
+            truncated = true;
+        }
+    }
+    if let Some(max_chars) = limit.max_chars {
+        if let Some((cut, _)) = text.char_indices().nth(max_chars) {
+            let head = &text[..cut];
+            let boundary = head
+                .char_indices()
+                .filter(|(_, c)| is_boundary(*c))
+                .map(|(index, c)| index + c.len_utf8())
+                .rfind(|&index| head[..index].chars().count() >= max_chars / 2);
+            text = &head[..boundary.unwrap_or(cut)];
+            truncated = true;
+        }
+    }
+    if truncated {
+        format!("{} {}", text.trim_end(), OMISSION)
+    } else {
+        text.to_string()
+    }
+}
+
 #[test]
 fn hiraganize_test() {
     let word = "hello".to_string();
@@ -213,3 +248,37 @@ fn code_block_test() {
     let text = "aaa ``` test ``` bbb".to_string();
     assert_eq!("aaa  bbb", text.remove_code_block());
 }
+
+#[test]
+fn truncate_test() {
+    let limit = |max_chars, max_lines| ReadLimit {
+        max_chars,
+        max_lines,
+    };
+    let text = "こんにちは。今日はいい天気ですね。散歩に行きましょう";
+    assert_eq!(truncate(text, limit(None, None)), text);
+    assert_eq!(truncate(text, limit(Some(100), None)), text);
+    // 文の切れ目で切る
+    assert_eq!(
+        truncate(text, limit(Some(20), None)),
+        "こんにちは。今日はいい天気ですね。 以下省略"
+    );
+    // 切れ目が前のほうにしかなければ上限の位置で切る
+    assert_eq!(
+        truncate("あ。いいいいいいいいいい", limit(Some(6), None)),
+        "あ。いいいい 以下省略"
+    );
+    assert_eq!(
+        truncate("1行目\n2行目\n3行目", limit(None, Some(2))),
+        "1行目\n2行目 以下省略"
+    );
+    // 最後の改行は行として数えない
+    assert_eq!(
+        truncate("1行目\n2行目\n", limit(None, Some(2))),
+        "1行目\n2行目"
+    );
+    assert_eq!(
+        truncate("1行目\n2行目です。続き", limit(Some(8), Some(5))),
+        "1行目 以下省略"
+    );
+}
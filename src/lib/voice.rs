@@ -1,103 +1,279 @@
-use std::{fs::File, io::Write};
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Result};
+use futures::FutureExt;
 use poise::{async_trait, serenity_prelude::Cache};
 use reqwest;
+use serde::Deserialize;
 use serenity::{
     client::Context,
-    model::id::GuildId,
+    model::id::{GuildId, UserId},
     utils::{content_safe, ContentSafeOptions},
 };
-use sqlx::{Pool, Sqlite};
-use tempfile;
+use songbird::input::Input;
 
-use super::text::TextMessage;
+use super::{
+    db::{DictContext, ReadLimit, VoiceType},
+    dictionary::Dictionary,
+    pipeline::{Enqueue, Slot},
+    queue::{self, Utterance, UtteranceKind},
+    text::{self, TextMessage},
+    wav,
+};
 
-#[derive(Debug)]
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// audio_query の speedScale, pitchScale, intonationScale に対応する値
+///
+/// 音量はエンジンによらず再生時に VoiceOptions::volume で変える
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeechParams {
+    pub speed: f64,
+    pub pitch: f64,
+    pub intonation: f64,
+}
+
+impl Default for SpeechParams {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            pitch: 0.0,
+            intonation: 1.0,
+        }
+    }
+}
+
+/// 読み上げ待ちが溜まっているときや長文のときに話速を上げるための設定
+#[derive(Debug, Clone, Copy)]
+pub struct SpeedScaling {
+    /// 読み上げ待ちの発話 1 つあたりに上げる倍率
+    pub per_queued: f64,
+    /// LONG_TEXT_LEN 文字を超えた 1 文字あたりに上げる倍率
+    pub per_char: f64,
+    /// 倍率の上限
+    pub max: f64,
+    /// 読み上げ待ちがこれより多くなったら古いものから捨てる
+    pub max_queued: Option<usize>,
+}
+
+impl SpeedScaling {
+    const LONG_TEXT_LEN: usize = 50;
+
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(key: &str) -> Option<T> {
+            std::env::var(key).ok().and_then(|v| v.parse().ok())
+        }
+        Self {
+            per_queued: var("SPEED_SCALE_PER_QUEUED").unwrap_or(0.1),
+            per_char: var("SPEED_SCALE_PER_CHAR").unwrap_or(0.005),
+            max: var("SPEED_SCALE_MAX").unwrap_or(1.5),
+            max_queued: var("MAX_QUEUED"),
+        }
+    }
+    /// 話速に掛ける倍率を [1, max] の範囲で返す
+    pub fn scale(&self, queue_len: usize, text_len: usize) -> f64 {
+        let long = text_len.saturating_sub(Self::LONG_TEXT_LEN);
+        (1. + self.per_queued * queue_len as f64 + self.per_char * long as f64)
+            .min(self.max)
+            .max(1.)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct VoiceOptions<T: VoiceGenerator> {
     generator: T,
+    voice_type: i64,
+    speech_params: SpeechParams,
     volume: f32,
-    speed_auto_scaling: bool,
+    speed_auto_scaling: Option<SpeedScaling>,
+    author_id: Option<UserId>,
+    kind: UtteranceKind,
 }
 
 #[derive(Debug)]
 pub struct TextOptions<'a, 'b, 'c> {
     clean: Option<&'a ContentSafeOptions>,
-    dict: Option<&'b Pool<Sqlite>>,
+    dict: Option<&'b Dictionary>,
+    dict_context: DictContext,
     read_name: Option<&'c String>,
+    limit: Option<ReadLimit>,
 }
 
 #[async_trait]
 pub trait VoiceGenerator {
-    async fn create_voice(&self, text: &str, file: &mut File) -> Result<()>;
+    /// WAV の音声を返す
+    async fn create_voice(
+        &self,
+        text: &str,
+        voice_type: i64,
+        params: &SpeechParams,
+    ) -> Result<Vec<u8>>;
+    async fn get_voice_types(&self, generator_type: i64) -> Result<Vec<VoiceType>>;
+    async fn health_check(&self) -> Result<()>;
+}
+
+#[async_trait]
+impl<T: VoiceGenerator + Send + Sync + ?Sized> VoiceGenerator for Arc<T> {
+    async fn create_voice(
+        &self,
+        text: &str,
+        voice_type: i64,
+        params: &SpeechParams,
+    ) -> Result<Vec<u8>> {
+        (**self).create_voice(text, voice_type, params).await
+    }
+    async fn get_voice_types(&self, generator_type: i64) -> Result<Vec<VoiceType>> {
+        (**self).get_voice_types(generator_type).await
+    }
+    async fn health_check(&self) -> Result<()> {
+        (**self).health_check().await
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct Style {
+    pub name: String,
+    pub id: u64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct Speaker {
+    pub name: String,
+    pub styles: Vec<Style>,
+}
+
+fn to_voice_types(speakers: Vec<Speaker>, generator_type: i64) -> Vec<VoiceType> {
+    let mut voice_types = Vec::new();
+    for speaker in speakers {
+        for style in speaker.styles {
+            voice_types.push(VoiceType {
+                name: speaker.name.clone(),
+                style_id: style.id,
+                style_name: style.name,
+                generator_type,
+            });
+        }
+    }
+    voice_types
 }
 
 pub struct VoiceVoxAPI {
     url: String,
-    voice_type: i64,
 }
 
 impl VoiceVoxAPI {
-    pub fn new(url: String, voice_type: i64) -> Self {
-        Self { url, voice_type }
+    pub fn new(url: String) -> Self {
+        Self { url }
     }
 }
 
 pub struct WebVoiceVoxAPI {
     url: String,
     api_key: String,
-    voice_type: i64,
 }
 
 impl WebVoiceVoxAPI {
-    pub fn new(url: String, api_key: String, voice_type: i64) -> Self {
-        Self {
-            url,
-            api_key,
-            voice_type,
-        }
+    pub fn new(url: String, api_key: String) -> Self {
+        Self { url, api_key }
     }
 }
 
 #[async_trait]
 impl VoiceGenerator for VoiceVoxAPI {
-    async fn create_voice(&self, text: &str, file: &mut File) -> Result<()> {
-        let params = [("text", text), ("speaker", &self.voice_type.to_string())];
+    async fn create_voice(
+        &self,
+        text: &str,
+        voice_type: i64,
+        params: &SpeechParams,
+    ) -> Result<Vec<u8>> {
+        let query = [("text", text), ("speaker", &voice_type.to_string())];
         let client = reqwest::Client::new();
         let voice_query_url = format!("{}/audio_query", self.url);
-        let res = client.post(voice_query_url).query(&params).send().await?;
-        let synthesis_body = res.text().await?;
-        let synthesis_arg = [("speaker", self.voice_type)];
+        let res = client.post(voice_query_url).query(&query).send().await?;
+        let mut audio_query: serde_json::Value = res.json().await?;
+        audio_query["speedScale"] = params.speed.into();
+        audio_query["pitchScale"] = params.pitch.into();
+        audio_query["intonationScale"] = params.intonation.into();
+        let synthesis_body = audio_query.to_string();
+        let synthesis_arg = [("speaker", voice_type)];
         let synthesis_url = format!("{}/synthesis", self.url);
         let synthesis_res = client
             .post(synthesis_url)
             .body(synthesis_body)
             .query(&synthesis_arg)
             .send()
-            .await?;
-        let _ = file.write(&synthesis_res.bytes().await?)?;
+            .await?
+            .error_for_status()?;
+        Ok(synthesis_res.bytes().await?.to_vec())
+    }
+    async fn get_voice_types(&self, generator_type: i64) -> Result<Vec<VoiceType>> {
+        let query_url = format!("{}/speakers", self.url);
+        let client = reqwest::Client::new();
+        let speakers = client.get(query_url).send().await?.json().await?;
+        Ok(to_voice_types(speakers, generator_type))
+    }
+    async fn health_check(&self) -> Result<()> {
+        let query_url = format!("{}/version", self.url);
+        let client = reqwest::Client::new();
+        client
+            .get(query_url)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
         Ok(())
     }
 }
 
 #[async_trait]
 impl VoiceGenerator for WebVoiceVoxAPI {
-    async fn create_voice(&self, text: &str, file: &mut File) -> Result<()> {
-        dotenv::dotenv().ok();
-        let params = [
+    async fn create_voice(
+        &self,
+        text: &str,
+        voice_type: i64,
+        params: &SpeechParams,
+    ) -> Result<Vec<u8>> {
+        let query = [
             ("key", self.api_key.as_str()),
             ("text", text),
-            ("speaker", &self.voice_type.to_string()),
+            ("speaker", &voice_type.to_string()),
+            ("speed", &params.speed.to_string()),
+            ("pitch", &params.pitch.to_string()),
+            ("intonationScale", &params.intonation.to_string()),
         ];
         let client = reqwest::Client::new();
         let res = client
-            .post(&self.url)
-            .query(&params)
+            .post(format!("{}/audio/", self.url))
+            .query(&query)
             .send()
             .await?
+            .error_for_status()?
             .bytes()
             .await?;
-        let _ = file.write(&res)?;
+        Ok(res.to_vec())
+    }
+    async fn get_voice_types(&self, generator_type: i64) -> Result<Vec<VoiceType>> {
+        let query_url = format!("{}/speakers/", self.url);
+        let client = reqwest::Client::new();
+        let speakers = client
+            .get(query_url)
+            .query(&[("key", &self.api_key)])
+            .send()
+            .await?
+            .json()
+            .await?;
+        Ok(to_voice_types(speakers, generator_type))
+    }
+    async fn health_check(&self) -> Result<()> {
+        let query_url = format!("{}/speakers/", self.url);
+        let client = reqwest::Client::new();
+        client
+            .get(query_url)
+            .query(&[("key", &self.api_key)])
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
         Ok(())
     }
 }
@@ -107,7 +283,9 @@ impl<'a, 'b, 'c> TextOptions<'a, 'b, 'c> {
         Self {
             clean: None,
             dict: None,
+            dict_context: DictContext::default(),
             read_name: None,
+            limit: None,
         }
     }
     pub fn read_name(&mut self, read_name: Option<&'c String>) -> &mut Self {
@@ -118,10 +296,19 @@ impl<'a, 'b, 'c> TextOptions<'a, 'b, 'c> {
         self.clean = clean;
         self
     }
-    pub fn dict(&mut self, dict: Option<&'b Pool<Sqlite>>) -> &mut Self {
+    pub fn dict(&mut self, dict: Option<&'b Dictionary>) -> &mut Self {
         self.dict = dict;
         self
     }
+    pub fn dict_context(&mut self, dict_context: DictContext) -> &mut Self {
+        self.dict_context = dict_context;
+        self
+    }
+    /// 辞書で置き換えたあとの文章をこの長さまでに切り詰める
+    pub fn limit(&mut self, limit: Option<ReadLimit>) -> &mut Self {
+        self.limit = limit;
+        self
+    }
     pub async fn format(&self, cache: &std::sync::Arc<Cache>, mut str: String) -> String {
         if let Some(read_name) = self.read_name {
             str = format!("{} {}", read_name, str);
@@ -130,7 +317,10 @@ impl<'a, 'b, 'c> TextOptions<'a, 'b, 'c> {
             str = content_safe(cache, str, options, &[]);
         }
         if let Some(dict) = self.dict {
-            str = str.make_read_text(dict).await;
+            str = str.make_read_text(dict, self.dict_context).await;
+        }
+        if let Some(limit) = self.limit {
+            str = text::truncate(&str, limit);
         }
         if str.chars().all(|c| !c.is_alphanumeric()) {
             str = "".to_string();
@@ -140,69 +330,169 @@ impl<'a, 'b, 'c> TextOptions<'a, 'b, 'c> {
 }
 
 impl<T: VoiceGenerator> VoiceOptions<T> {
-    pub fn new(voice_generator: T) -> Self {
+    pub fn new(voice_generator: T, voice_type: i64) -> Self {
         Self {
             generator: voice_generator,
+            voice_type,
+            speech_params: SpeechParams::default(),
             volume: 1.,
-            speed_auto_scaling: false,
+            speed_auto_scaling: None,
+            author_id: None,
+            kind: UtteranceKind::Chat,
         }
     }
-    pub fn speed_auto_scaling(&mut self, flag: bool) -> &mut Self {
-        self.speed_auto_scaling = flag;
+    pub fn speech_params(&mut self, speech_params: SpeechParams) -> &mut Self {
+        self.speech_params = speech_params;
+        self
+    }
+    pub fn volume(&mut self, volume: f32) -> &mut Self {
+        self.volume = volume;
         self
     }
+    pub fn speed_auto_scaling(&mut self, scaling: SpeedScaling) -> &mut Self {
+        self.speed_auto_scaling = Some(scaling);
+        self
+    }
+    /// /queue で表示する話した人
+    pub fn author(&mut self, author_id: UserId) -> &mut Self {
+        self.author_id = Some(author_id);
+        self
+    }
+    /// チャット以外は読み上げ待ちのチャットより先に読む
+    pub fn kind(&mut self, kind: UtteranceKind) -> &mut Self {
+        self.kind = kind;
+        self
+    }
+
+    /// 音声を合成して再生待ちに入れる
+    pub async fn play_voice(&self, ctx: &Context, guild_id: GuildId, str: String) -> Result<()> {
+        if let Some(synthesized) = self.synthesize(ctx, guild_id, str).await? {
+            synthesized.enqueue(ctx, guild_id).await?;
+        }
+        Ok(())
+    }
 
-    pub async fn play_voice<'a, 'b, 'c>(
+    /// 音声を合成する。空の文章のときやボイスチャンネルにいないときは None
+    pub async fn synthesize(
         &self,
         ctx: &Context,
         guild_id: GuildId,
         str: String,
-    ) -> Result<()> {
+    ) -> Result<Option<Synthesized>> {
         // tracing::info!("voice setting: {:?}", &self);
         if str.is_empty() {
-            return Ok(());
+            return Ok(None);
+        }
+        let manager = songbird::get(ctx)
+            .await
+            .ok_or_else(|| anyhow!("Songbird Voice client placed in at initialisation."))?
+            .clone();
+        let handler_lock = match manager.get(guild_id) {
+            Some(handler_lock) => handler_lock,
+            None => return Ok(None),
+        };
+        let mut speech_params = self.speech_params;
+        if let Some(scaling) = &self.speed_auto_scaling {
+            let queue_len = handler_lock.lock().await.queue().len();
+            speech_params.speed *= scaling.scale(queue_len, str.chars().count());
         }
-        let mut temp_file = tempfile::Builder::new().tempfile_in("temp")?;
-        self.generator
-            .create_voice(&str, temp_file.as_file_mut())
+        let audio = self
+            .generator
+            .create_voice(&str, self.voice_type, &speech_params)
             .await?;
+        Ok(Some(Synthesized {
+            source: wav::decode(&audio)?,
+            volume: self.volume,
+            utterance: Utterance {
+                author_id: self.author_id,
+                text: str,
+                kind: self.kind,
+            },
+            max_queued: self.speed_auto_scaling.and_then(|s| s.max_queued),
+        }))
+    }
+}
+
+impl<T: VoiceGenerator + Clone + Send + Sync + 'static> VoiceOptions<T> {
+    /// 合成して、slot の順番で再生待ちに入れる。エラーはログに出すだけ
+    pub fn play_voice_in_order(&self, slot: Slot, ctx: &Context, guild_id: GuildId, str: String) {
+        let options = self.clone();
+        let ctx = ctx.clone();
+        slot.fill(
+            async move {
+                let synthesized = options.synthesize(&ctx, guild_id, str).await;
+                Box::new(move || {
+                    async move {
+                        let result = match synthesized {
+                            Ok(Some(synthesized)) => synthesized.enqueue(&ctx, guild_id).await,
+                            Ok(None) => Ok(()),
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            tracing::info!("error: {}", e);
+                        }
+                    }
+                    .boxed()
+                }) as Enqueue
+            }
+            .boxed(),
+        );
+    }
+}
 
-        let (_, path) = temp_file.keep()?;
+/// 合成が終わって、再生待ちに入れるだけになった発話
+pub struct Synthesized {
+    source: Input,
+    volume: f32,
+    utterance: Utterance,
+    max_queued: Option<usize>,
+}
+
+impl Synthesized {
+    /// guild_id の再生待ちに入れる。もうボイスチャンネルにいなければ捨てる
+    pub async fn enqueue(self, ctx: &Context, guild_id: GuildId) -> Result<()> {
         let manager = songbird::get(ctx)
             .await
             .ok_or_else(|| anyhow!("Songbird Voice client placed in at initialisation."))?
             .clone();
-        if let Some(handler_lock) = manager.get(guild_id) {
-            let mut handler = handler_lock.lock().await;
-            let mut source = songbird::ffmpeg(&path).await?;
-            source.metadata.source_url = Some(path.to_string_lossy().to_string());
-            let (mut track, _) = songbird::tracks::create_player(source);
-            track.set_volume(self.volume);
-            handler.enqueue(track);
+        let handler_lock = match manager.get(guild_id) {
+            Some(handler_lock) => handler_lock,
+            None => return Ok(()),
+        };
+        let mut handler = handler_lock.lock().await;
+        let (mut track, track_handle) = songbird::tracks::create_player(self.source);
+        track.set_volume(self.volume);
+        track_handle
+            .typemap()
+            .write()
+            .await
+            .insert::<Utterance>(self.utterance);
+        handler.enqueue(track);
+        queue::prioritize_last(handler.queue());
+        if let Some(max_queued) = self.max_queued {
+            queue::drop_overflow(handler.queue(), max_queued);
         }
         Ok(())
     }
 }
 
-// pub async fn play_raw_voice(
-//     ctx: &Context,
-//     str: &str,
-//     voice_type: u32,
-//     generator_type: usize,
-//     guild_id: GuildId,
-// ) -> Result<()> {
-//     let mut temp_file = tempfile::Builder::new().tempfile_in("temp")?;
-//     create_voice(str, voice_type, generator_type, temp_file.as_file_mut()).await?;
-//     let (_, path) = temp_file.keep()?;
-//     let manager = songbird::get(ctx)
-//         .await
-//         .ok_or_else(|| anyhow!("Songbird Voice client placed in at initialisation."))?
-//         .clone();
-//     if let Some(handler_lock) = manager.get(guild_id) {
-//         let mut handler = handler_lock.lock().await;
-//         let mut source = songbird::ffmpeg(&path).await?;
-//         source.metadata.source_url = Some(path.to_string_lossy().to_string());
-//         handler.enqueue_source(source);
-//     }
-//     Ok(())
-// }
+#[test]
+fn speed_scaling_test() {
+    let scaling = SpeedScaling {
+        per_queued: 0.1,
+        per_char: 0.01,
+        max: 1.5,
+        max_queued: None,
+    };
+    assert_eq!(scaling.scale(0, 10), 1.);
+    assert!((scaling.scale(3, 10) - 1.3).abs() < 1e-9);
+    assert!((scaling.scale(0, 70) - 1.2).abs() < 1e-9);
+    assert_eq!(scaling.scale(100, 1000), 1.5);
+
+    // 上限が 1 未満でも遅くはしない
+    let scaling = SpeedScaling {
+        max: 0.5,
+        ..scaling
+    };
+    assert_eq!(scaling.scale(3, 10), 1.);
+}
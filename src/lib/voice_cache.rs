@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use poise::async_trait;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use super::{
+    db::VoiceType,
+    generator::Generator,
+    voice::{SpeechParams, VoiceGenerator},
+};
+
+/// VOICE_CACHE_SIZE_MB を設定しなかったときのキャッシュの大きさ
+const DEFAULT_SIZE_MB: usize = 64;
+
+type Key = [u8; 32];
+
+/// 合成した音声を (エンジン, スタイル, 話し方, 文章) のハッシュで引けるようにしておくキャッシュ
+///
+/// 合計の大きさが max_bytes を超えたら、最後に使ったのが古いものから捨てる
+#[derive(Debug)]
+pub struct VoiceCache {
+    max_bytes: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<Key, CacheEntry>,
+    /// 最後に使った順番から Key を引く
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+    bytes: usize,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    audio: Vec<u8>,
+    used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// 空白の違いだけの文章は同じものとして扱う
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn cache_key(generator_type: i64, voice_type: i64, params: &SpeechParams, text: &str) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(generator_type.to_le_bytes());
+    hasher.update(voice_type.to_le_bytes());
+    hasher.update(params.speed.to_bits().to_le_bytes());
+    hasher.update(params.pitch.to_bits().to_le_bytes());
+    hasher.update(params.intonation.to_bits().to_le_bytes());
+    hasher.update(normalize(text).as_bytes());
+    hasher.finalize().into()
+}
+
+impl CacheInner {
+    fn touch(&mut self, key: Key) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.recency.remove(&entry.used);
+            entry.used = self.tick;
+            self.recency.insert(self.tick, key);
+        }
+    }
+}
+
+impl VoiceCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+    pub fn from_env() -> Self {
+        let size_mb = std::env::var("VOICE_CACHE_SIZE_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SIZE_MB);
+        Self::new(size_mb * 1024 * 1024)
+    }
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+    pub fn get(&self, key: &Key) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock().unwrap();
+        let audio = inner.entries.get(key).map(|entry| entry.audio.clone());
+        match audio {
+            Some(_) => {
+                inner.stats.hits += 1;
+                inner.touch(*key);
+            }
+            None => inner.stats.misses += 1,
+        }
+        audio
+    }
+    /// max_bytes より大きい音声は入れない
+    pub fn insert(&self, key: Key, audio: Vec<u8>) {
+        if audio.len() > self.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(old) = inner.entries.remove(&key) {
+            inner.recency.remove(&old.used);
+            inner.bytes -= old.audio.len();
+        }
+        while inner.bytes + audio.len() > self.max_bytes {
+            let (_, oldest) = match inner.recency.pop_first() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.bytes -= evicted.audio.len();
+                inner.stats.evictions += 1;
+            }
+        }
+        inner.tick += 1;
+        let used = inner.tick;
+        inner.bytes += audio.len();
+        inner.recency.insert(used, key);
+        inner.entries.insert(key, CacheEntry { audio, used });
+    }
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+            ..inner.stats
+        }
+    }
+}
+
+/// create_voice の前にキャッシュを引く音声合成エンジン
+pub struct CachedGenerator {
+    inner: Generator,
+    generator_type: i64,
+    cache: Arc<VoiceCache>,
+}
+
+impl CachedGenerator {
+    pub fn new(inner: Generator, generator_type: i64, cache: Arc<VoiceCache>) -> Self {
+        Self {
+            inner,
+            generator_type,
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl VoiceGenerator for CachedGenerator {
+    async fn create_voice(
+        &self,
+        text: &str,
+        voice_type: i64,
+        params: &SpeechParams,
+    ) -> Result<Vec<u8>> {
+        let key = cache_key(self.generator_type, voice_type, params, text);
+        if let Some(audio) = self.cache.get(&key) {
+            let stats = self.cache.stats();
+            info!(
+                "voice cache hit (hits {}, misses {})",
+                stats.hits, stats.misses
+            );
+            return Ok(audio);
+        }
+        let audio = self.inner.create_voice(text, voice_type, params).await?;
+        self.cache.insert(key, audio.clone());
+        let stats = self.cache.stats();
+        info!(
+            "voice cache miss (hits {}, misses {}, {} entries, {} bytes)",
+            stats.hits, stats.misses, stats.entries, stats.bytes
+        );
+        Ok(audio)
+    }
+    async fn get_voice_types(&self, generator_type: i64) -> Result<Vec<VoiceType>> {
+        self.inner.get_voice_types(generator_type).await
+    }
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+}
+
+#[test]
+fn cache_key_test() {
+    let params = SpeechParams::default();
+    let key = cache_key(0, 1, &params, "草");
+    assert_eq!(key, cache_key(0, 1, &params, " 草\n"));
+    assert_ne!(key, cache_key(1, 1, &params, "草"));
+    assert_ne!(key, cache_key(0, 2, &params, "草"));
+    assert_ne!(key, cache_key(0, 1, &params, "w"));
+    let faster = SpeechParams {
+        speed: 1.1,
+        ..params
+    };
+    assert_ne!(key, cache_key(0, 1, &faster, "草"));
+}
+
+#[test]
+fn lru_test() {
+    let cache = VoiceCache::new(10);
+    let key = |text: &str| cache_key(0, 0, &SpeechParams::default(), text);
+    cache.insert(key("a"), vec![0; 4]);
+    cache.insert(key("b"), vec![0; 4]);
+    // a を使ったので、次に捨てられるのは b
+    assert!(cache.get(&key("a")).is_some());
+    cache.insert(key("c"), vec![0; 4]);
+    assert!(cache.get(&key("b")).is_none());
+    assert!(cache.get(&key("a")).is_some());
+    assert!(cache.get(&key("c")).is_some());
+    // 大きすぎるものは入れない
+    cache.insert(key("d"), vec![0; 11]);
+    assert!(cache.get(&key("d")).is_none());
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            hits: 3,
+            misses: 2,
+            evictions: 1,
+            entries: 2,
+            bytes: 8,
+        }
+    );
+}
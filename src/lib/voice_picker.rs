@@ -0,0 +1,290 @@
+use poise::serenity_prelude::{ButtonStyle, CreateComponents};
+
+use super::db::VoiceType;
+
+/// /set_voice_type で表示するコンポーネントの custom_id の接頭辞
+pub const VOICE_TYPE_MENU: &str = "voice_type";
+
+/// select menu 1 つに入れられる選択肢の上限
+const PAGE_SIZE: usize = 25;
+
+/// 試聴ボタンを押したときに読み上げる文章
+pub const PREVIEW_TEXT: &str = "こんにちは。この声で読み上げるよ";
+
+pub enum PickerAction {
+    /// 話者一覧の page ページ目を表示する
+    Page(usize),
+    /// page ページ目から話者が選ばれた
+    Speaker(usize),
+    /// page ページ目の話者のスタイルが選ばれた
+    Style(usize),
+    /// voice_key で指定されたボイスを試聴する
+    Preview(String),
+    /// voice_key で指定されたボイスに決定する
+    Confirm(String),
+}
+
+pub struct PickerView {
+    pub content: String,
+    pub components: CreateComponents,
+}
+
+pub fn parse_action(custom_id: &str) -> Option<PickerAction> {
+    let (prefix, rest) = custom_id.split_once(':')?;
+    if prefix != VOICE_TYPE_MENU {
+        return None;
+    }
+    let (kind, arg) = rest.split_once(':')?;
+    match kind {
+        "page" => arg.parse().ok().map(PickerAction::Page),
+        "speaker" => arg.parse().ok().map(PickerAction::Speaker),
+        "style" => arg.parse().ok().map(PickerAction::Style),
+        "preview" => Some(PickerAction::Preview(arg.to_string())),
+        "confirm" => Some(PickerAction::Confirm(arg.to_string())),
+        _ => None,
+    }
+}
+
+/// select menu の value に入れる、voice_types の並びに依存しないボイスタイプのキー
+pub fn voice_key(voice_type: &VoiceType) -> String {
+    format!("{}:{}", voice_type.generator_type, voice_type.style_id)
+}
+
+pub fn find_voice_type<'a>(voice_types: &'a [VoiceType], key: &str) -> Option<&'a VoiceType> {
+    let (generator_type, style_id) = key.split_once(':')?;
+    let generator_type = generator_type.parse::<i64>().ok()?;
+    let style_id = style_id.parse::<u64>().ok()?;
+    voice_types.iter().find(|voice_type| {
+        voice_type.generator_type == generator_type && voice_type.style_id == style_id
+    })
+}
+
+fn speaker_key(generator_type: i64, name: &str) -> String {
+    format!("{}:{}", generator_type, name)
+}
+
+/// (generator_type, 話者名) の一覧をエンジンごとにまとめて返す
+fn speakers(voice_types: &[VoiceType]) -> Vec<(i64, &str)> {
+    let mut speakers: Vec<(i64, &str)> = Vec::new();
+    for voice_type in voice_types {
+        let speaker = (voice_type.generator_type, voice_type.name.as_str());
+        if !speakers.contains(&speaker) {
+            speakers.push(speaker);
+        }
+    }
+    speakers.sort_by_key(|(generator_type, _)| *generator_type);
+    speakers
+}
+
+fn page_count(len: usize) -> usize {
+    len.div_ceil(PAGE_SIZE).max(1)
+}
+
+pub fn speaker_page(voice_types: &[VoiceType], page: usize) -> PickerView {
+    let speakers = speakers(voice_types);
+    let pages = page_count(speakers.len());
+    let page = page.min(pages - 1);
+    let mut components = CreateComponents::default();
+    components.create_action_row(|row| {
+        row.create_select_menu(|menu| {
+            menu.custom_id(format!("{}:speaker:{}", VOICE_TYPE_MENU, page))
+                .placeholder("話者")
+                .options(|os| {
+                    for (generator_type, name) in
+                        speakers.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE)
+                    {
+                        os.create_option(|op| {
+                            op.label(name)
+                                .value(speaker_key(*generator_type, name))
+                                .description(format!("エンジン {}", generator_type))
+                        });
+                    }
+                    os
+                })
+        })
+    });
+    if pages > 1 {
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .custom_id(format!(
+                        "{}:page:{}",
+                        VOICE_TYPE_MENU,
+                        page.saturating_sub(1)
+                    ))
+                    .label("前へ")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0)
+            })
+            .create_button(|button| {
+                button
+                    .custom_id(format!("{}:page:{}", VOICE_TYPE_MENU, page + 1))
+                    .label("次へ")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 >= pages)
+            })
+        });
+    }
+    PickerView {
+        content: format!("話者を選んでね ({}/{})", page + 1, pages),
+        components,
+    }
+}
+
+/// speaker_key で指定された話者のスタイル一覧を表示する。話者が見つからなければ None
+pub fn style_page(voice_types: &[VoiceType], speaker_key: &str, page: usize) -> Option<PickerView> {
+    style_page_with_selection(voice_types, speaker_key, page, None)
+}
+
+/// voice_type を選択した状態のスタイル一覧を、試聴ボタンと決定ボタンをつけて表示する
+pub fn selected_style_page(
+    voice_types: &[VoiceType],
+    voice_type: &VoiceType,
+    page: usize,
+) -> Option<PickerView> {
+    style_page_with_selection(
+        voice_types,
+        &speaker_key(voice_type.generator_type, &voice_type.name),
+        page,
+        Some(voice_type),
+    )
+}
+
+fn style_page_with_selection(
+    voice_types: &[VoiceType],
+    speaker_key: &str,
+    page: usize,
+    selected: Option<&VoiceType>,
+) -> Option<PickerView> {
+    let styles = voice_types
+        .iter()
+        .filter(|voice_type| {
+            self::speaker_key(voice_type.generator_type, &voice_type.name) == speaker_key
+        })
+        .take(PAGE_SIZE)
+        .collect::<Vec<_>>();
+    let name = &styles.first()?.name;
+    let selected_key = selected.map(voice_key);
+    let mut components = CreateComponents::default();
+    components
+        .create_action_row(|row| {
+            row.create_select_menu(|menu| {
+                menu.custom_id(format!("{}:style:{}", VOICE_TYPE_MENU, page))
+                    .placeholder("スタイル")
+                    .options(|os| {
+                        for style in styles.iter() {
+                            let key = voice_key(style);
+                            os.create_option(|op| {
+                                op.label(&style.style_name)
+                                    .default_selection(selected_key.as_ref() == Some(&key))
+                                    .value(key)
+                            });
+                        }
+                        os
+                    })
+            })
+        })
+        .create_action_row(|row| {
+            if let Some(key) = &selected_key {
+                row.create_button(|button| {
+                    button
+                        .custom_id(format!("{}:preview:{}", VOICE_TYPE_MENU, key))
+                        .label("試聴")
+                        .style(ButtonStyle::Secondary)
+                })
+                .create_button(|button| {
+                    button
+                        .custom_id(format!("{}:confirm:{}", VOICE_TYPE_MENU, key))
+                        .label("決定")
+                        .style(ButtonStyle::Primary)
+                });
+            }
+            row.create_button(|button| {
+                button
+                    .custom_id(format!("{}:page:{}", VOICE_TYPE_MENU, page))
+                    .label("話者選択に戻る")
+                    .style(ButtonStyle::Secondary)
+            })
+        });
+    let content = match selected {
+        Some(voice_type) => format!(
+            "{} {} にする？試聴ボタンでボイスチャンネルに声を流せるよ",
+            voice_type.name, voice_type.style_name
+        ),
+        None => format!("{} のスタイルを選んでね", name),
+    };
+    Some(PickerView {
+        content,
+        components,
+    })
+}
+
+#[cfg(test)]
+fn voice_type(generator_type: i64, name: &str, style_id: u64) -> VoiceType {
+    VoiceType {
+        name: name.to_string(),
+        style_id,
+        style_name: format!("style {}", style_id),
+        generator_type,
+    }
+}
+
+#[test]
+fn voice_key_test() {
+    let voice_types = vec![
+        voice_type(0, "四国めたん", 2),
+        voice_type(1, "つくよみちゃん", 2),
+    ];
+    for voice_type in voice_types.iter() {
+        let found = find_voice_type(&voice_types, &voice_key(voice_type)).unwrap();
+        assert_eq!(found.name, voice_type.name);
+    }
+    assert!(find_voice_type(&voice_types, "2:2").is_none());
+    assert!(find_voice_type(&voice_types, "0").is_none());
+    assert!(find_voice_type(&voice_types, "3").is_none());
+}
+
+#[test]
+fn speakers_test() {
+    let voice_types = vec![
+        voice_type(1, "つくよみちゃん", 0),
+        voice_type(0, "四国めたん", 2),
+        voice_type(0, "四国めたん", 0),
+        voice_type(0, "ずんだもん", 1),
+        // 別のエンジンの同名の話者は別の話者として扱う
+        voice_type(1, "四国めたん", 5),
+    ];
+    assert_eq!(
+        speakers(&voice_types),
+        vec![
+            (0, "四国めたん"),
+            (0, "ずんだもん"),
+            (1, "つくよみちゃん"),
+            (1, "四国めたん")
+        ]
+    );
+    assert!(style_page(&voice_types, "0:四国めたん", 0).is_some());
+    assert!(style_page(&voice_types, "2:四国めたん", 0).is_none());
+}
+
+#[test]
+fn page_test() {
+    assert_eq!(page_count(0), 1);
+    assert_eq!(page_count(25), 1);
+    assert_eq!(page_count(26), 2);
+    assert!(matches!(
+        parse_action("voice_type:page:3"),
+        Some(PickerAction::Page(3))
+    ));
+    assert!(matches!(
+        parse_action("voice_type:style:0"),
+        Some(PickerAction::Style(0))
+    ));
+    assert!(matches!(
+        parse_action("voice_type:confirm:1:3"),
+        Some(PickerAction::Confirm(key)) if key == "1:3"
+    ));
+    assert!(parse_action("voice_type:speaker").is_none());
+    assert!(parse_action("voice_type:0").is_none());
+    assert!(parse_action("other:page:0").is_none());
+}
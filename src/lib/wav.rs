@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use songbird::input::{Codec, Container, Input, Metadata, Reader};
+
+/// songbird が再生するサンプリング周波数
+const SAMPLE_RATE: u32 = 48_000;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug)]
+struct Wav {
+    sample_rate: u32,
+    channels: u16,
+    /// チャンネルごとに交互に並んだ [-1, 1] のサンプル
+    samples: Vec<f32>,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn decode_sample(format: u16, bits: u16, sample: &[u8]) -> Option<f32> {
+    Some(match (format, bits) {
+        (FORMAT_PCM, 8) => (sample[0] as f32 - 128.) / 128.,
+        (FORMAT_PCM, 16) => i16::from_le_bytes(sample.try_into().ok()?) as f32 / 32768.,
+        (FORMAT_PCM, 24) => {
+            (i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8) as f32 / 8388608.
+        }
+        (FORMAT_PCM, 32) => i32::from_le_bytes(sample.try_into().ok()?) as f32 / 2147483648.,
+        (FORMAT_FLOAT, 32) => f32::from_le_bytes(sample.try_into().ok()?),
+        _ => return None,
+    })
+}
+
+fn parse(bytes: &[u8]) -> Result<Wav> {
+    if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+        return Err(anyhow!("not a wav file"));
+    }
+    let mut format = None;
+    let mut offset = 12;
+    while let (Some(id), Some(size)) = (bytes.get(offset..offset + 4), u32_at(bytes, offset + 4)) {
+        let start = offset + 8;
+        // ストリーミングで書かれた WAV は data の大きさが実際と合わないことがある
+        let end = start.saturating_add(size as usize).min(bytes.len());
+        let chunk = &bytes[start..end];
+        match id {
+            b"fmt " => {
+                let mut tag = u16_at(chunk, 0).ok_or_else(|| anyhow!("broken fmt chunk"))?;
+                if tag == FORMAT_EXTENSIBLE {
+                    tag = u16_at(chunk, 24).ok_or_else(|| anyhow!("broken fmt chunk"))?;
+                }
+                let channels = u16_at(chunk, 2).ok_or_else(|| anyhow!("broken fmt chunk"))?;
+                let sample_rate = u32_at(chunk, 4).ok_or_else(|| anyhow!("broken fmt chunk"))?;
+                let bits = u16_at(chunk, 14).ok_or_else(|| anyhow!("broken fmt chunk"))?;
+                format = Some((tag, channels, sample_rate, bits));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, bits) =
+                    format.ok_or_else(|| anyhow!("data chunk before fmt chunk"))?;
+                if channels == 0 || sample_rate == 0 || bits % 8 != 0 {
+                    return Err(anyhow!("unsupported wav format"));
+                }
+                let samples = chunk
+                    .chunks_exact(bits as usize / 8)
+                    .map(|sample| decode_sample(tag, bits, sample))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| anyhow!("unsupported wav format {} ({} bits)", tag, bits))?;
+                return Ok(Wav {
+                    sample_rate,
+                    channels,
+                    samples,
+                });
+            }
+            _ => {}
+        }
+        // チャンクは 2 バイト単位で並ぶ
+        offset = end + (end - start) % 2;
+    }
+    Err(anyhow!("data chunk not found"))
+}
+
+fn to_mono(wav: &Wav) -> Vec<f32> {
+    if wav.channels == 1 {
+        return wav.samples.clone();
+    }
+    wav.samples
+        .chunks_exact(wav.channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / wav.channels as f32)
+        .collect()
+}
+
+/// 線形補間で from Hz から to Hz に変換する
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * to as u64 / from as u64) as usize;
+    let step = from as f64 / to as f64;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let next = samples.get(index + 1).unwrap_or(&samples[index]);
+            let t = (position - index as f64) as f32;
+            samples[index] * (1. - t) + next * t
+        })
+        .collect()
+}
+
+/// 音声合成エンジンが返した WAV を、ファイルに書かずにそのまま再生できる Input にする
+pub fn decode(bytes: &[u8]) -> Result<Input> {
+    let wav = parse(bytes)?;
+    let samples = resample(&to_mono(&wav), wav.sample_rate, SAMPLE_RATE);
+    let metadata = Metadata {
+        duration: Some(Duration::from_secs_f64(
+            samples.len() as f64 / SAMPLE_RATE as f64,
+        )),
+        sample_rate: Some(SAMPLE_RATE),
+        channels: Some(1),
+        ..Default::default()
+    };
+    let buffer = samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect();
+    Ok(Input::new(
+        false,
+        Reader::from_memory(buffer),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(metadata),
+    ))
+}
+
+#[cfg(test)]
+fn wav_bytes(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let data = samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    bytes.extend(b"RIFF");
+    bytes.extend((36 + data.len() as u32).to_le_bytes());
+    bytes.extend(b"WAVE");
+    bytes.extend(b"fmt ");
+    bytes.extend(16u32.to_le_bytes());
+    bytes.extend(FORMAT_PCM.to_le_bytes());
+    bytes.extend(channels.to_le_bytes());
+    bytes.extend(sample_rate.to_le_bytes());
+    bytes.extend((sample_rate * channels as u32 * 2).to_le_bytes());
+    bytes.extend((channels * 2).to_le_bytes());
+    bytes.extend(16u16.to_le_bytes());
+    // 読み飛ばすチャンク。奇数の大きさなので 1 バイト詰め物が入る
+    bytes.extend(b"LIST");
+    bytes.extend(3u32.to_le_bytes());
+    bytes.extend([0, 0, 0, 0]);
+    bytes.extend(b"data");
+    bytes.extend((data.len() as u32).to_le_bytes());
+    bytes.extend(data);
+    bytes
+}
+
+#[test]
+fn parse_test() {
+    let wav = parse(&wav_bytes(24_000, 2, &[16384, -16384, 0, 32767])).unwrap();
+    assert_eq!(wav.sample_rate, 24_000);
+    assert_eq!(wav.channels, 2);
+    assert_eq!(wav.samples.len(), 4);
+    assert_eq!(to_mono(&wav), vec![0., 32767. / 65536.]);
+    assert!(parse(b"RIFF\0\0\0\0WAVE").is_err());
+    assert!(parse(b"not a wav").is_err());
+}
+
+#[test]
+fn resample_test() {
+    assert_eq!(resample(&[0., 1.], 24_000, 48_000), vec![0., 0.5, 1., 1.]);
+    assert_eq!(resample(&[0., 0.5, 1., 1.], 48_000, 24_000), vec![0., 1.]);
+    assert_eq!(resample(&[0.25], 48_000, 48_000), vec![0.25]);
+    let input = decode(&wav_bytes(24_000, 1, &[0; 2400])).unwrap();
+    assert_eq!(input.metadata.duration, Some(Duration::from_millis(100)));
+}
@@ -1,13 +1,17 @@
 use crate::{
     lib::{
-        db::UserConfigDB,
-        text::TextMessage,
-        voice::{TextOptions, VoiceOptions, WebVoiceVoxAPI},
+        db::{DictContext, DictDB, ReadFilterDB, ReadLimit, ReadLimitDB, UserConfigDB, VoiceType},
+        dict_view,
+        dictionary::Dictionary,
+        queue::{self, UtteranceKind},
+        voice::{TextOptions, VoiceOptions},
+        voice_picker::{self, PickerAction, PickerView},
     },
     Data,
 };
 use anyhow::{anyhow, Result};
 use poise::serenity_prelude::{self as serenity, MessageComponentInteraction, VoiceState};
+use std::sync::Arc;
 use tracing::info;
 
 pub async fn event_listener(
@@ -22,18 +26,18 @@ pub async fn event_listener(
         poise::Event::VoiceStateUpdate { old, new } => {
             voice_state_update(ctx, old, new, user_data).await?
         }
-        poise::Event::InteractionCreate { interaction } => match interaction {
-            serenity::Interaction::MessageComponent(message_component) => {
-                select_menu(ctx, message_component, user_data).await?;
-            }
-            _ => (),
-        },
+        poise::Event::InteractionCreate {
+            interaction: serenity::Interaction::MessageComponent(message_component),
+        } => {
+            select_menu(ctx, message_component, user_data).await?;
+            dict_list_page(ctx, message_component, user_data).await?;
+        }
         _ => {}
     }
     Ok(())
 }
 
-async fn ready(ctx: &serenity::Context, ready: &serenity::Ready) {
+async fn ready(_ctx: &serenity::Context, ready: &serenity::Ready) {
     // let old_global_commands = ctx.http.get_global_application_commands().await.unwrap();
     // for command in old_global_commands {
     //     dbg!(command.name);
@@ -45,8 +49,58 @@ async fn ready(ctx: &serenity::Context, ready: &serenity::Ready) {
     info!("{} is connected!", ready.user.name);
 }
 
+/// 読み上げない人に設定されている人の発言か、bot を読まない設定のサーバーでの bot や webhook の発言なら true
+async fn is_ignored(message: &serenity::Message, user_data: &Data) -> Result<bool> {
+    let guild_id = match message.guild_id {
+        Some(guild_id) => guild_id.0 as i64,
+        None => return Ok(true),
+    };
+    if (message.author.bot || message.webhook_id.is_some())
+        && user_data.database.get_skip_bots(guild_id).await?
+    {
+        return Ok(true);
+    }
+    user_data
+        .database
+        .is_ignored(guild_id, message.author.id.0 as i64)
+        .await
+}
+
 async fn message(ctx: &serenity::Context, message: &serenity::Message, user_data: &Data) {
     info!("{:?}", message);
+    let guild = match message.guild(&ctx.cache) {
+        Some(guild) => guild,
+        None => return,
+    };
+    let bot_id = ctx.cache.current_user_id();
+    // bot がボイスチャンネルにいなければ読まない
+    let voice_channel_id = guild
+        .voice_states
+        .get(&bot_id)
+        .and_then(|voice_states| voice_states.channel_id);
+    if message.author.id == bot_id || voice_channel_id.is_none() {
+        return;
+    }
+    // 発言が届いた順に読むため、await する前に合成の順番を取っておく。読まない発言なら drop して飛ばす
+    let slot = user_data.synthesis.reserve(guild.id);
+    let is_read_channel = user_data
+        .sessions
+        .lock()
+        .await
+        .get(&guild.id)
+        .is_some_and(|session| session.is_read_channel(message.channel_id));
+    if !is_read_channel {
+        return;
+    }
+    // 読み上げる発言のときだけデータベースを引く
+    match is_ignored(message, user_data).await {
+        Ok(false) => {}
+        Ok(true) => return,
+        Err(e) => {
+            info!("error: {}", e);
+            return;
+        }
+    }
     let user_config = crate::lib::db::UserConfigDB::get_user_config_or_default(
         &user_data.database,
         message.author.id.0 as i64,
@@ -54,53 +108,54 @@ async fn message(ctx: &serenity::Context, message: &serenity::Message, user_data
     .await
     .unwrap();
     let voice_type = user_config.voice_type;
-    let generator_type = user_config.generator_type;
+    let speech_params = user_config.speech_params();
+    let volume = user_config.volume as f32;
     let nickname = user_config.read_nickname.unwrap_or_else(|| {
+        // webhook の発言には member がない
         message
             .member
             .as_ref()
-            .unwrap()
-            .nick
-            .as_ref()
+            .and_then(|member| member.nick.as_ref())
             .unwrap_or(&message.author.name)
             .to_string()
     });
     info!("{:?}", &nickname);
-    let guild = message.guild(&ctx.cache).unwrap();
-    let bot_id = ctx.cache.current_user_id();
-    let voice_channel_id = guild
-        .voice_states
-        .get(&bot_id)
-        .and_then(|voice_states| voice_states.channel_id);
-    let text_channel_id = message.channel_id;
-    let read_channel_id = *user_data.read_channel_id.lock().await;
-    dotenv::dotenv();
-    let api_key = std::env::var("WEB_API_KEY").unwrap();
-    let web = WebVoiceVoxAPI::new(
-        "https://api.su-shiki.com/v2/voicevox/audio/".to_string(),
-        api_key,
-        voice_type,
-    );
+    let (generator, voice_type) = match user_data
+        .generators
+        .resolve(user_config.generator_type, voice_type)
+    {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            info!("error: {}", e);
+            return;
+        }
+    };
+    let limit = match user_data.database.get_read_limit(guild.id.0 as i64).await {
+        Ok(limit) => limit,
+        Err(e) => {
+            info!("error: {}", e);
+            ReadLimit::default()
+        }
+    };
     let text = TextOptions::new()
         .clean(Some(&serenity::ContentSafeOptions::new()))
-        .dict(Some(&user_data.database))
+        .dict(Some(&user_data.dictionary))
+        .dict_context(DictContext {
+            guild_id: Some(guild.id.0 as i64),
+            user_id: Some(message.author.id.0 as i64),
+        })
         .read_name(Some(&nickname))
+        .limit(Some(limit))
         .format(&ctx.cache, message.content.clone())
         .await;
     dbg!(&text);
-    if read_channel_id == Some(text_channel_id) {
-        if let Some(_voice_channel_id) = voice_channel_id {
-            if message.author.id != bot_id {
-                if let Err(e) = VoiceOptions::new(web)
-                    .speed_auto_scaling(true)
-                    .play_voice(ctx, guild.id, text)
-                    .await
-                {
-                    info!("error: {}", e)
-                };
-            };
-        }
-    }
+    // 合成の速さによらず発言の順に読む
+    VoiceOptions::new(generator, voice_type)
+        .speech_params(speech_params)
+        .volume(volume)
+        .speed_auto_scaling(user_data.speed_scaling)
+        .author(message.author.id)
+        .play_voice_in_order(slot, ctx, guild.id, text);
 }
 
 async fn voice_state_update(
@@ -114,6 +169,22 @@ async fn voice_state_update(
         .guild_id
         .ok_or_else(|| anyhow!("new guild id not found"))?;
 
+    if new.user_id == *bot_id {
+        let mut sessions = user_data.sessions.lock().await;
+        match new.channel_id {
+            Some(channel_id) => {
+                if let Some(session) = sessions.get_mut(&guild_id) {
+                    session.voice_channel_id = channel_id;
+                }
+            }
+            None => {
+                sessions.remove(&guild_id);
+                user_data.synthesis.remove(guild_id);
+            }
+        }
+        return Ok(());
+    }
+
     let bot_channel_id = guild_id
         .to_guild_cached(&ctx.cache)
         .ok_or_else(|| anyhow!("new guild not found"))?
@@ -143,14 +214,11 @@ async fn voice_state_update(
         if has_handler {
             manager.remove(guild_id).await?;
         }
+        user_data.sessions.lock().await.remove(&guild_id);
         return Ok(());
     }
 
     let user_id = new.user_id;
-
-    if bot_id.0 == user_id.0 {
-        return Ok(());
-    }
     let new_member = new
         .member
         .as_ref()
@@ -178,6 +246,17 @@ async fn voice_state_update(
     } else {
         return Ok(());
     };
+    // 入ってすぐ出た人の入室時のあいさつがまだ読まれていなければ、どちらのあいさつもしない
+    if greeting_type == 1 {
+        if let Some(handler_lock) = songbird::get(ctx)
+            .await
+            .and_then(|manager| manager.get(guild_id))
+        {
+            if queue::drop_hello(handler_lock.lock().await.queue(), user_id) {
+                return Ok(());
+            }
+        }
+    }
 
     let uid = user_id.0 as i64;
     let user_config = user_data
@@ -185,6 +264,8 @@ async fn voice_state_update(
         .get_user_config_or_default(uid)
         .await
         .unwrap();
+    let speech_params = user_config.speech_params();
+    let volume = user_config.volume as f32;
     let nickname = user_config
         .read_nickname
         .unwrap_or_else(|| user_name.to_string());
@@ -193,56 +274,235 @@ async fn voice_state_update(
         1 => user_config.bye,
         _ => unreachable!(),
     };
-    let text = format!("{}さん、{}", nickname, greet_text)
-        .make_read_text(&user_data.database)
-        .await;
-    let voice_type = user_config.voice_type;
-    let generator_type = user_config.generator_type;
-    dotenv::dotenv();
-    let api_key = std::env::var("WEB_API_KEY")?;
-    let web = WebVoiceVoxAPI::new(
-        "https://api.su-shiki.com/v2/voicevox/audio/".to_string(),
-        api_key,
-        voice_type,
-    );
-    let text = TextOptions::new()
-        .clean(Some(&serenity::ContentSafeOptions::new()))
-        .dict(Some(&user_data.database))
-        .format(&ctx.cache, text)
-        .await;
-    if let Err(e) = VoiceOptions::new(web).play_voice(ctx, guild_id, text).await {
+    let (generator, voice_type) = user_data
+        .generators
+        .resolve(user_config.generator_type, user_config.voice_type)?;
+    let text = greeting_text(
+        &ctx.cache,
+        &user_data.dictionary,
+        DictContext {
+            guild_id: Some(guild_id.0 as i64),
+            user_id: Some(uid),
+        },
+        &nickname,
+        &greet_text,
+    )
+    .await;
+    if let Err(e) = VoiceOptions::new(generator, voice_type)
+        .speech_params(speech_params)
+        .volume(volume)
+        .author(user_id)
+        .kind(if greeting_type == 0 {
+            UtteranceKind::Hello
+        } else {
+            UtteranceKind::Bye
+        })
+        .play_voice(ctx, guild_id, text)
+        .await
+    {
         info!("{}", e);
     };
     Ok(())
 }
 
+/// あいさつの読み上げる文章。辞書での置き換えは format の中で 1 回だけ行う
+async fn greeting_text(
+    cache: &Arc<serenity::Cache>,
+    dictionary: &Dictionary,
+    dict_context: DictContext,
+    nickname: &str,
+    greet_text: &str,
+) -> String {
+    TextOptions::new()
+        .clean(Some(&serenity::ContentSafeOptions::new()))
+        .dict(Some(dictionary))
+        .dict_context(dict_context)
+        .format(cache, format!("{}さん、{}", nickname, greet_text))
+        .await
+}
+
 async fn select_menu(
     ctx: &serenity::Context,
     message_component: &MessageComponentInteraction,
     user_data: &Data,
 ) -> Result<()> {
-    let idx = message_component.data.values[0].parse::<usize>().unwrap();
-    let voice_type = &user_data.voice_types.lock().await[idx];
-    let generator_type = voice_type.generator_type;
-    let style_id = voice_type.style_id;
-    let user_id = message_component.user.id.0;
-    let mut user_config = user_data
-        .database
-        .get_user_config_or_default(user_id as i64)
-        .await
-        .unwrap();
-    user_config.generator_type = generator_type;
-    user_config.voice_type = style_id as i64;
-    user_data
-        .database
-        .update_user_config(&user_config)
-        .await
-        .unwrap();
-    let res = message_component
+    let action = match voice_picker::parse_action(&message_component.data.custom_id) {
+        Some(action) => action,
+        None => return Ok(()),
+    };
+    let voice_types = user_data.voice_types.lock().await.clone();
+    let view = match action {
+        PickerAction::Page(page) => Some(voice_picker::speaker_page(&voice_types, page)),
+        PickerAction::Speaker(page) => message_component
+            .data
+            .values
+            .first()
+            .and_then(|key| voice_picker::style_page(&voice_types, key, page)),
+        PickerAction::Style(page) => message_component
+            .data
+            .values
+            .first()
+            .and_then(|key| voice_picker::find_voice_type(&voice_types, key))
+            .and_then(|voice_type| {
+                voice_picker::selected_style_page(&voice_types, voice_type, page)
+            }),
+        PickerAction::Preview(key) => {
+            let voice_type = voice_picker::find_voice_type(&voice_types, &key);
+            return preview_voice(ctx, message_component, user_data, voice_type).await;
+        }
+        PickerAction::Confirm(key) => {
+            if let Some(voice_type) = voice_picker::find_voice_type(&voice_types, &key) {
+                let user_id = message_component.user.id.0;
+                let mut user_config = user_data
+                    .database
+                    .get_user_config_or_default(user_id as i64)
+                    .await?;
+                user_config.generator_type = voice_type.generator_type;
+                user_config.voice_type = voice_type.style_id as i64;
+                user_data.database.update_user_config(&user_config).await?;
+                Some(PickerView {
+                    content: format!("{} {} に設定したよ", voice_type.name, voice_type.style_name),
+                    components: serenity::CreateComponents::default(),
+                })
+            } else {
+                None
+            }
+        }
+    };
+    match view {
+        Some(view) => {
+            message_component
+                .create_interaction_response(&ctx.http, |res| {
+                    res.kind(serenity::InteractionResponseType::UpdateMessage)
+                        .interaction_response_data(|msg| {
+                            msg.content(view.content).set_components(view.components)
+                        })
+                })
+                .await?;
+        }
+        None => {
+            reply_ephemeral(
+                ctx,
+                message_component,
+                "このメニューは古くなってるよ。もう一度 /set_voice_type してね",
+            )
+            .await?;
+        }
+    };
+    Ok(())
+}
+
+async fn dict_list_page(
+    ctx: &serenity::Context,
+    message_component: &MessageComponentInteraction,
+    user_data: &Data,
+) -> Result<()> {
+    let (scope, page) = match dict_view::parse_action(&message_component.data.custom_id) {
+        Some(action) => action,
+        None => return Ok(()),
+    };
+    let dicts = user_data.dictionary.get_dict_all(scope).await?;
+    let view = dict_view::list_page(scope, dicts, page);
+    message_component
         .create_interaction_response(&ctx.http, |res| {
             res.kind(serenity::InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|msg| {
+                    msg.set_embed(view.embed).set_components(view.components)
+                })
         })
+        .await?;
+    Ok(())
+}
+
+async fn preview_voice(
+    ctx: &serenity::Context,
+    message_component: &MessageComponentInteraction,
+    user_data: &Data,
+    voice_type: Option<&VoiceType>,
+) -> Result<()> {
+    let guild_id = message_component
+        .guild_id
+        .ok_or_else(|| anyhow!("guild id not found"))?;
+    let voice_type = match voice_type {
+        Some(voice_type) => voice_type,
+        None => {
+            return reply_ephemeral(
+                ctx,
+                message_component,
+                "このメニューは古くなってるよ。もう一度 /set_voice_type してね",
+            )
+            .await;
+        }
+    };
+    if !user_data.sessions.lock().await.contains_key(&guild_id) {
+        return reply_ephemeral(
+            ctx,
+            message_component,
+            "ボイスチャンネルに入ってないと試聴できないよ",
+        )
         .await;
-    info!("{:?}", res);
+    }
+    if !user_data.generators.is_healthy(voice_type.generator_type) {
+        return reply_ephemeral(ctx, message_component, "そのエンジンは今使えないよ").await;
+    }
+    message_component
+        .create_interaction_response(&ctx.http, |res| {
+            res.kind(serenity::InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await?;
+    let user_config = user_data
+        .database
+        .get_user_config_or_default(message_component.user.id.0 as i64)
+        .await?;
+    let (generator, voice_type) = user_data
+        .generators
+        .resolve(voice_type.generator_type, voice_type.style_id as i64)?;
+    VoiceOptions::new(generator, voice_type)
+        .speech_params(user_config.speech_params())
+        .volume(user_config.volume as f32)
+        .author(message_component.user.id)
+        .kind(UtteranceKind::Announcement)
+        .play_voice(ctx, guild_id, voice_picker::PREVIEW_TEXT.to_string())
+        .await
+}
+
+async fn reply_ephemeral(
+    ctx: &serenity::Context,
+    message_component: &MessageComponentInteraction,
+    content: &str,
+) -> Result<()> {
+    message_component
+        .create_interaction_response(&ctx.http, |res| {
+            res.kind(serenity::InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|msg| msg.content(content).ephemeral(true))
+        })
+        .await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn greeting_text_test() {
+    use crate::lib::db::{self, DictScope, TEST_EDITOR};
+
+    let dictionary = Dictionary::new(db::memory_database().await);
+    // 置き換えた後の「ねっぷ」をさらに「のっぷ」に置き換えない
+    for (word, read_word) in [("なっぷ", "ねっぷ"), ("ねっぷ", "のっぷ")] {
+        dictionary
+            .update_dict(
+                DictScope::Global,
+                &crate::Dict::literal(word, read_word),
+                TEST_EDITOR,
+            )
+            .await
+            .unwrap();
+    }
+    let text = greeting_text(
+        &Arc::new(serenity::Cache::default()),
+        &dictionary,
+        DictContext::default(),
+        "なっぷ",
+        "こんにちは",
+    )
+    .await;
+    assert_eq!(text, "ねっぷさん、こんにちは");
+}
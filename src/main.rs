@@ -1,3 +1,4 @@
+#![allow(special_module_name)]
 mod commands;
 
 mod lib;
@@ -6,42 +7,30 @@ use dotenv::dotenv;
 use poise::serenity_prelude as serenity;
 type Context<'a> = poise::Context<'a, Data, anyhow::Error>;
 
-use songbird::{Event, EventContext};
-use std::path::Path;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::info;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Dict {
     word: String,
     read_word: String,
+    match_mode: lib::db::MatchMode,
 }
 
-struct TrackEndNotifier;
 pub struct Data {
     pub database: sqlx::SqlitePool,
-    pub read_channel_id: Arc<Mutex<Option<serenity::model::id::ChannelId>>>,
+    pub dictionary: lib::dictionary::Dictionary,
+    pub sessions: Arc<Mutex<HashMap<serenity::GuildId, lib::session::Session>>>,
     pub voice_types: Arc<Mutex<Vec<lib::db::VoiceType>>>,
+    pub generators: Arc<lib::generator::Generators>,
+    pub speed_scaling: lib::voice::SpeedScaling,
+    pub synthesis: lib::pipeline::SynthesisPipeline,
 }
 
-#[poise::async_trait]
-impl songbird::EventHandler for TrackEndNotifier {
-    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        if let EventContext::Track(track_list) = ctx {
-            for (_, handle) in track_list.iter() {
-                let path = handle.metadata().source_url.as_ref().unwrap();
-                tracing::info!("played file path: {:?}", path);
-                if !path.ends_with(".wav") {
-                    std::fs::remove_file(Path::new(handle.metadata().source_url.as_ref().unwrap()))
-                        .unwrap();
-                }
-            }
-        }
-        None
-    }
-}
 #[poise::command(slash_command)]
 async fn connect(ctx: Context<'_>) -> anyhow::Result<()> {
     let guild_id = ctx.guild_id().unwrap();
@@ -65,6 +54,41 @@ async fn connect(ctx: Context<'_>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// コマンドのチェックで断られたときは、その人の言語で必要な権限を返事する
+async fn on_error(error: poise::FrameworkError<'_, Data, anyhow::Error>) {
+    match error {
+        poise::FrameworkError::CommandCheckFailed { ctx, error: None } => {
+            let rules = ctx
+                .invocation_data::<Vec<lib::db::PermissionRule>>()
+                .await
+                .map(|rules| rules.clone())
+                .unwrap_or_default();
+            let text = lib::permission::refusal(&rules, ctx.locale());
+            if let Err(e) = ctx
+                .send(|m| {
+                    m.content(text)
+                        .ephemeral(true)
+                        .allowed_mentions(|mentions| mentions.empty_roles())
+                })
+                .await
+            {
+                info!("{:?}", e)
+            }
+        }
+        poise::FrameworkError::NotAnOwner { ctx } => {
+            let text = lib::permission::owner_refusal(ctx.locale());
+            if let Err(e) = ctx.send(|m| m.content(text).ephemeral(true)).await {
+                info!("{:?}", e)
+            }
+        }
+        error => {
+            if let Err(e) = poise::builtins::on_error(error).await {
+                info!("{:?}", e)
+            }
+        }
+    }
+}
+
 #[poise::command(prefix_command)]
 async fn register(ctx: Context<'_>) -> anyhow::Result<()> {
     poise::builtins::register_application_commands_buttons(ctx).await?;
@@ -88,9 +112,32 @@ async fn main() {
         .run(&database)
         .await
         .expect("Couldn't run database migrations");
-    let voice_types = lib::db::get_voice_types_by_web_voicevox_api()
+    let dictionary = lib::dictionary::Dictionary::load(database.clone())
         .await
-        .expect("Couldn't get voice types");
+        .expect("Couldn't load dictionary");
+    let generators =
+        Arc::new(lib::generator::Generators::load().expect("Couldn't load generators"));
+    let voice_types = Arc::new(Mutex::new(generators.get_voice_types(&[]).await));
+    // 0 秒ごとには確認できないので既定値を使う
+    let health_check_interval = std::env::var("HEALTH_CHECK_INTERVAL")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(60);
+    generators.spawn_health_check(Duration::from_secs(health_check_interval));
+    // 0 なら読み込み直さない
+    if let Some(voice_reload_interval) = std::env::var("VOICE_RELOAD_INTERVAL")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|&secs| secs > 0)
+    {
+        lib::generator::spawn_voice_reload(
+            generators.clone(),
+            voice_types.clone(),
+            database.clone(),
+            Duration::from_secs(voice_reload_interval),
+        );
+    }
     let _application_id: String = std::env::var("APP_ID").unwrap().parse().unwrap();
     let token = std::env::var("DISCORD_TOKEN").expect("environment variable not found");
     let framework = poise::Framework::builder()
@@ -101,17 +148,29 @@ async fn main() {
                 commands::meta::leave(),
                 commands::meta::mute(),
                 commands::meta::unmute(),
+                commands::meta::skip(),
+                commands::meta::stop(),
+                commands::meta::queue(),
+                commands::read::read(),
                 commands::user_config::set_hello(),
                 commands::user_config::set_bye(),
                 commands::user_config::set_nickname(),
                 commands::user_config::set_voice_type(),
+                commands::user_config::voice(),
                 commands::dict::add(),
                 commands::dict::rem(),
+                commands::dict::dict(),
+                commands::admin::reload_voices(),
+                commands::admin::cache_stats(),
+                commands::config::config(),
+                commands::ignore::ignore(),
+                commands::ignore::optout(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
                 prefix: Some("~".into()),
                 ..Default::default()
             },
+            on_error: |error| Box::pin(on_error(error)),
             listener: |ctx, event, framework, user_data| {
                 Box::pin(listener::event_listener(ctx, event, framework, user_data))
             },
@@ -124,17 +183,18 @@ async fn main() {
         .user_data_setup(move |_ctx, _ready, _framework| {
             Box::pin(async move {
                 Ok(Data {
+                    dictionary,
                     database,
-                    read_channel_id: Arc::new(Mutex::new(None)),
-                    voice_types: Arc::new(Mutex::new(voice_types)),
+                    sessions: Arc::new(Mutex::new(HashMap::new())),
+                    voice_types,
+                    generators,
+                    speed_scaling: lib::voice::SpeedScaling::from_env(),
+                    synthesis: lib::pipeline::SynthesisPipeline::from_env(),
                 })
             })
         })
         .client_settings(songbird::register);
-    std::fs::create_dir("temp").ok();
     if let Err(e) = framework.run().await {
         info!("{:?}", e)
     };
-    std::fs::remove_dir_all("temp").unwrap();
-    std::fs::create_dir("temp").unwrap();
 }